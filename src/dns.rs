@@ -2,21 +2,26 @@ pub mod codec;
 pub mod request;
 pub mod response;
 pub mod server;
+pub mod writer;
 
-pub use codec::Codec;
+pub use codec::{Codec, TcpCodec};
 pub use request::Request;
 pub use response::Response;
 pub use server::Server;
+pub use writer::MessageWriter;
 
 use bytes::BufMut;
 use serde::{Deserialize, Serialize};
 
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct RawHeader {
     pub id: u16,
-    // pub qr: Qr,
+    pub qr: Qr,
     pub opcode: OpCode,
     pub truncated: bool,
     pub authoritative_answer: bool,
@@ -49,16 +54,170 @@ pub struct StartOfAuthority {
     pub minimum: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+/// Domain names are compared case-insensitively, but keep their original
+/// case, see [RFC 4343](https://www.rfc-editor.org/rfc/rfc4343).
+/// Like all other operations, comparisons work on [`DomainName::labels`], so
+/// a fully qualified name with a trailing dot equals the same name without.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DomainName(String);
 
+impl PartialEq for DomainName {
+    fn eq(&self, other: &Self) -> bool {
+        let mut labels = self.labels();
+        let mut other_labels = other.labels();
+        loop {
+            match (labels.next(), other_labels.next()) {
+                (Some(label), Some(other_label)) if label.eq_ignore_ascii_case(other_label) => {}
+                (None, None) => return true,
+                _ => return false,
+            }
+        }
+    }
+}
+
+impl Eq for DomainName {}
+
+impl Hash for DomainName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in self.labels() {
+            for byte in label.bytes() {
+                state.write_u8(byte.to_ascii_lowercase());
+            }
+            // never part of UTF-8, so it keeps labels apart
+            state.write_u8(0xff);
+        }
+    }
+}
+
 impl DomainName {
+    /// Splits the name at all dots that are not escaped.
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        let mut labels = vec![];
+        let mut start = 0;
+        let mut escaped = false;
+        for (i, c) in self.0.char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '.' if !escaped => {
+                    labels.push(&self.0[start..i]);
+                    start = i + 1;
+                }
+                _ => escaped = false,
+            }
+        }
+        labels.push(&self.0[start..]);
+
+        labels.into_iter().filter(|label| !label.is_empty())
+    }
+
+    /// The name without its first label, if there is more than one label.
+    pub fn parent(&self) -> Option<DomainName> {
+        let labels: Vec<&str> = self.labels().skip(1).collect();
+        if labels.is_empty() {
+            return None;
+        }
+
+        Some(DomainName(labels.join(".")))
+    }
+
+    /// Checks the hostname syntax of
+    /// [RFC 1123](https://www.rfc-editor.org/rfc/rfc1123#section-2.1): labels
+    /// of letters, digits and hyphens, neither starting nor ending with a
+    /// hyphen.
+    pub fn is_valid_hostname(&self) -> bool {
+        let mut labels = self.labels().peekable();
+        if labels.peek().is_none() || self.0.len() > 253 {
+            return false;
+        }
+
+        labels.all(is_hostname_label)
+    }
+
+    /// Like [`DomainName::is_valid_hostname`], but additionally allows
+    /// leading underscore labels of service names, e.g. `_sip._tcp.<host>`
+    /// or `_acme-challenge.<host>`, see
+    /// [RFC 8552](https://www.rfc-editor.org/rfc/rfc8552#section-2), and a
+    /// leading `*` label of wildcards, see
+    /// [RFC 4592](https://www.rfc-editor.org/rfc/rfc4592#section-2.1.1).
+    pub fn is_valid_owner_name(&self) -> bool {
+        let mut labels = self.labels().peekable();
+        if labels.peek().is_none() || self.0.len() > 253 {
+            return false;
+        }
+
+        labels.next_if_eq(&"*");
+
+        labels
+            .skip_while(|label| {
+                label
+                    .strip_prefix('_')
+                    .is_some_and(|label| !label.is_empty() && is_hostname_label(label))
+            })
+            .all(is_hostname_label)
+    }
+
+    /// Whether the name is equal to `apex` or below it.
+    pub fn is_subdomain_of(&self, apex: &DomainName) -> bool {
+        self == apex || self.strip_suffix(apex).is_some()
+    }
+
+    /// The labels in front of `suffix`, if the name ends with all labels of
+    /// `suffix`. Labels are compared as a whole, so an escaped dot within a
+    /// label never separates a suffix.
     pub fn strip_suffix(&self, suffix: &DomainName) -> Option<DomainName> {
-        self.0
-            .strip_suffix(&suffix.0)?
-            .strip_suffix(".")
-            .map(String::from)
-            .map(DomainName)
+        let labels: Vec<&str> = self.labels().collect();
+        let suffix_labels: Vec<&str> = suffix.labels().collect();
+
+        let prefix_len = labels.len().checked_sub(suffix_labels.len())?;
+        let (prefix, tail) = labels.split_at(prefix_len);
+        if prefix.is_empty()
+            || !tail
+                .iter()
+                .zip(&suffix_labels)
+                .all(|(label, suffix_label)| label.eq_ignore_ascii_case(suffix_label))
+        {
+            return None;
+        }
+
+        Some(DomainName(prefix.join(".")))
+    }
+}
+
+fn is_hostname_label(label: &str) -> bool {
+    label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// The name of the PTR record of an address, see
+/// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-3.5) and
+/// [RFC 3596](https://www.rfc-editor.org/rfc/rfc3596#section-2.5).
+pub fn reverse_name(addr: IpAddr) -> DomainName {
+    let labels: Vec<String> = match addr {
+        IpAddr::V4(addr) => addr
+            .octets()
+            .iter()
+            .rev()
+            .map(u8::to_string)
+            .chain(["in-addr".to_string(), "arpa".to_string()])
+            .collect(),
+        IpAddr::V6(addr) => addr
+            .octets()
+            .iter()
+            .rev()
+            .flat_map(|octet| [octet & 0x0f, octet >> 4])
+            .map(|nibble| format!("{nibble:x}"))
+            .chain(["ip6".to_string(), "arpa".to_string()])
+            .collect(),
+    };
+
+    DomainName(labels.join("."))
+}
+
+impl fmt::Display for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
@@ -74,14 +233,36 @@ impl From<String> for DomainName {
     }
 }
 
+/// EDNS(0) information carried by the OPT pseudo-record, see
+/// [RFC 6891](https://www.rfc-editor.org/rfc/rfc6891#section-6.1.2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum QueryType {
     A,
     NS,
     MX,
     SOA,
+    PTR,
     AAAA,
     CNAME,
+    TXT,
+    SRV,
+    OPT,
+    CAA,
     NotImplmented(u16),
     ALL,
 }
@@ -92,6 +273,11 @@ pub enum QueryClass {
     CS,
     CH,
     HS,
+    /// Only used in update messages, see
+    /// [RFC 2136](https://www.rfc-editor.org/rfc/rfc2136#section-2.4)
+    NONE,
+    ANY,
+    Unknown(u16),
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -114,68 +300,137 @@ pub enum ResourceRecord {
         ttl: u32,
         addr: Ipv6Addr,
     },
-    SOA(StartOfAuthority),
+    NS {
+        name: Name,
+        ttl: u32,
+        nsdname: DomainName,
+    },
+    CNAME {
+        name: Name,
+        ttl: u32,
+        cname: DomainName,
+    },
+    TXT {
+        name: Name,
+        ttl: u32,
+        text: String,
+    },
+    MX {
+        name: Name,
+        ttl: u32,
+        preference: u16,
+        exchange: DomainName,
+    },
+    SRV {
+        name: Name,
+        ttl: u32,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: DomainName,
+    },
+    CAA {
+        name: Name,
+        ttl: u32,
+        flags: u8,
+        tag: String,
+        value: String,
+    },
+    PTR {
+        name: Name,
+        ttl: u32,
+        ptrdname: DomainName,
+    },
+    SOA {
+        ttl: u32,
+        soa: StartOfAuthority,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Name {
     Labels(Vec<String>),
     Pointer(LabelPointer),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LabelPointer(u16);
 
+impl From<&DomainName> for Name {
+    fn from(name: &DomainName) -> Self {
+        Name::Labels(name.labels().map(String::from).collect())
+    }
+}
+
 impl LabelPointer {
     pub fn new(offset: u16) -> Self {
         Self(offset)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Qr {
     Query,
     Response,
 }
 
-impl From<ResourceRecord> for Vec<u8> {
-    fn from(rr: ResourceRecord) -> Self {
-        let mut raw_rr = Vec::new();
-        match rr {
-            ResourceRecord::A { name, ttl, addr } => {
-                raw_rr.append(&mut name.into());
-                raw_rr.put_u16(QueryType::A.into());
-                raw_rr.put_u16(QueryClass::IN.into());
-                raw_rr.put_u32(ttl);
-
-                // IPv4 addr consists of 4 byte octets
-                raw_rr.put_u16(4);
-                raw_rr.put_u32(addr.into());
+/// Escapes all octets of a label that can't be written as plain text in the
+/// way master files do it, see
+/// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-5.1): dots and
+/// backslashes are prefixed with a backslash, non-printable octets are
+/// written as `\DDD`.
+pub fn escape_label(octets: &[u8]) -> String {
+    let mut label = String::with_capacity(octets.len());
+    for &octet in octets {
+        match octet {
+            b'.' | b'\\' => {
+                label.push('\\');
+                label.push(char::from(octet));
             }
-            ResourceRecord::AAAA { name, ttl, addr } => {
-                raw_rr.append(&mut name.into());
-                raw_rr.put_u16(QueryType::AAAA.into());
-                raw_rr.put_u16(QueryClass::IN.into());
-                raw_rr.put_u32(ttl);
-
-                // IPv6 addr consists of 16 byte octets
-                raw_rr.put_u16(16);
-                for s in addr.segments() {
-                    raw_rr.put_u16(s);
-                }
+            0x21..=0x7e => label.push(char::from(octet)),
+            _ => label.push_str(&format!("\\{octet:03}")),
+        }
+    }
+    label
+}
+
+/// Reverses [`escape_label`] to get the octets of a label for the wire.
+pub fn label_octets(label: &str) -> Vec<u8> {
+    let label = label.as_bytes();
+    let mut octets = Vec::with_capacity(label.len());
+
+    let mut i = 0;
+    while i < label.len() {
+        if label[i] != b'\\' {
+            octets.push(label[i]);
+            i += 1;
+            continue;
+        }
+
+        let decimal = label
+            .get(i + 1..i + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_digit))
+            .and_then(|digits| str::from_utf8(digits).ok()?.parse::<u8>().ok());
+        match decimal {
+            Some(octet) => {
+                octets.push(octet);
+                i += 4;
             }
-            ResourceRecord::SOA(soa) => {
-                raw_rr.append(&mut soa.mname.clone().into());
-                raw_rr.put_u16(QueryType::SOA.into());
-                raw_rr.put_u16(QueryClass::IN.into());
-                raw_rr.put_u32(soa.minimum);
-
-                let rdata = Vec::<u8>::from(soa);
-                raw_rr.put_u16(rdata.len() as u16);
-                raw_rr.extend(rdata)
+            None => {
+                octets.extend(label.get(i + 1));
+                i += 2;
             }
         }
-        raw_rr
+    }
+
+    octets
+}
+
+impl From<ResourceRecord> for Vec<u8> {
+    fn from(rr: ResourceRecord) -> Self {
+        let mut writer = MessageWriter::without_compression();
+        writer.write_record(&rr);
+        writer.into()
     }
 }
 
@@ -186,7 +441,7 @@ impl From<RawHeader> for Vec<u8> {
         raw_header.put_u16(header.id);
 
         let mut flags: u16 = 0;
-        flags |= (u16::from(Qr::Response) << 15) & 0b1000000000000000;
+        flags |= (u16::from(header.qr) << 15) & 0b1000000000000000;
         flags |= (u16::from(u8::from(header.opcode)) << 11) & 0b0111100000000000;
         flags |= ((header.authoritative_answer as u16) << 10) & 0b0000010000000000;
         flags |= ((header.truncated as u16) << 9) & 0b0000001000000000;
@@ -205,59 +460,42 @@ impl From<RawHeader> for Vec<u8> {
 
 impl From<Question> for Vec<u8> {
     fn from(question: Question) -> Self {
-        let mut raw: Vec<u8> = question
-            .labels
-            .iter()
-            .flat_map(|x| {
-                let mut r = vec![x.len() as u8];
-                r.put(x.as_bytes());
-                r
-            })
-            .collect();
-
-        raw.put_u8(0);
-
-        raw.put_u16(question.query_type.into());
-        raw.put_u16(question.query_class.into());
-
-        raw
+        let mut writer = MessageWriter::without_compression();
+        writer.write_question(&question);
+        writer.into()
     }
 }
 
-impl From<StartOfAuthority> for Vec<u8> {
-    fn from(soa: StartOfAuthority) -> Self {
+impl From<Edns> for Vec<u8> {
+    fn from(edns: Edns) -> Self {
         let mut raw = vec![];
 
-        raw.extend(Vec::<u8>::from(soa.mname));
-        raw.extend(Vec::<u8>::from(soa.rname));
-        raw.put_u32(soa.serial);
-        raw.put_u32(soa.refresh);
-        raw.put_u32(soa.retry);
-        raw.put_u32(soa.expire);
-        raw.put_u32(soa.minimum);
+        // the OPT record is always owned by the root domain
+        raw.put_u8(0);
+        raw.put_u16(QueryType::OPT.into());
+        raw.put_u16(edns.udp_payload_size);
+        raw.put_u8(edns.extended_rcode);
+        raw.put_u8(edns.version);
+        raw.put_u16((edns.dnssec_ok as u16) << 15);
+
+        let rdata: Vec<u8> = edns
+            .options
+            .into_iter()
+            .flat_map(|option| {
+                let mut raw_option = vec![];
+                raw_option.put_u16(option.code);
+                raw_option.put_u16(option.data.len() as u16);
+                raw_option.extend(option.data);
+                raw_option
+            })
+            .collect();
+        raw.put_u16(rdata.len() as u16);
+        raw.extend(rdata);
 
         raw
     }
 }
 
-impl From<DomainName> for Vec<u8> {
-    fn from(name: DomainName) -> Self {
-        name.0
-            .split(".")
-            .flat_map(|s| {
-                let mut raw = vec![];
-
-                let bytes = s.as_bytes();
-                raw.put_u8(bytes.len() as u8);
-                raw.extend_from_slice(bytes);
-
-                raw
-            })
-            .chain([0u8]) // null terminated string
-            .collect()
-    }
-}
-
 impl From<u8> for OpCode {
     fn from(code: u8) -> Self {
         match code {
@@ -289,8 +527,13 @@ impl From<u16> for QueryType {
             5 => QueryType::CNAME,
             6 => QueryType::SOA,
             // NOTE: [7, 8, 9, 10] experiemental
+            12 => QueryType::PTR,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            257 => QueryType::CAA,
+            41 => QueryType::OPT,
             255 => QueryType::ALL,
             t => QueryType::NotImplmented(t),
         }
@@ -306,8 +549,13 @@ impl From<QueryType> for u16 {
             QueryType::CNAME => 5,
             QueryType::SOA => 6,
             // NOTE: [7, 8, 9, 10] experiemental
+            QueryType::PTR => 12,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::CAA => 257,
+            QueryType::OPT => 41,
             QueryType::ALL => 255,
             QueryType::NotImplmented(t) => t,
         }
@@ -321,7 +569,9 @@ impl From<u16> for QueryClass {
             2 => QueryClass::CS,
             3 => QueryClass::CH,
             4 => QueryClass::HS,
-            _ => panic!("This should be an error :)"),
+            254 => QueryClass::NONE,
+            255 => QueryClass::ANY,
+            _ => QueryClass::Unknown(value),
         }
     }
 }
@@ -333,6 +583,9 @@ impl From<QueryClass> for u16 {
             QueryClass::CS => 2,
             QueryClass::CH => 3,
             QueryClass::HS => 4,
+            QueryClass::NONE => 254,
+            QueryClass::ANY => 255,
+            QueryClass::Unknown(value) => value,
         }
     }
 }
@@ -344,8 +597,9 @@ impl From<Name> for Vec<u8> {
         match name {
             Name::Labels(labels) => {
                 labels.iter().for_each(|l| {
-                    raw_name.put_u8(l.len() as u8);
-                    raw_name.put(l.as_bytes());
+                    let octets = label_octets(l);
+                    raw_name.put_u8(octets.len() as u8);
+                    raw_name.put(octets.as_slice());
                 });
                 // null termination
                 raw_name.put_u8(0);
@@ -373,3 +627,185 @@ impl From<Qr> for u16 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_caa_record() {
+        let rr = ResourceRecord::CAA {
+            name: Name::Labels(vec!["dyn".to_string()]),
+            ttl: 15,
+            flags: 128,
+            tag: "issue".to_string(),
+            value: "letsencrypt.org".to_string(),
+        };
+
+        let raw = Vec::<u8>::from(rr);
+
+        // owner
+        assert_eq!(&raw[..5], b"\x03dyn\x00");
+        // type, class, ttl
+        assert_eq!(&raw[5..13], &[1, 1, 0, 1, 0, 0, 0, 15]);
+        // rdata
+        assert_eq!(&raw[13..15], &[0, 2 + 5 + 15]);
+        assert_eq!(&raw[15..], b"\x80\x05issueletsencrypt.org");
+    }
+
+    #[test]
+    fn it_encodes_caa_record_with_empty_value() {
+        let rr = ResourceRecord::CAA {
+            name: Name::Labels(vec!["dyn".to_string()]),
+            ttl: 15,
+            flags: 0,
+            tag: "issue".to_string(),
+            value: String::new(),
+        };
+
+        let raw = Vec::<u8>::from(rr);
+
+        assert_eq!(&raw[13..], b"\x00\x07\x00\x05issue");
+    }
+
+    #[test]
+    fn it_converts_unknown_query_classes() {
+        for value in [1, 254, 255, 42] {
+            assert_eq!(u16::from(QueryClass::from(value)), value);
+        }
+        assert_eq!(QueryClass::from(255), QueryClass::ANY);
+        assert_eq!(QueryClass::from(42), QueryClass::Unknown(42));
+    }
+
+    #[test]
+    fn it_escapes_labels() {
+        let octets = b"a.b\\ \xff_-";
+
+        let label = escape_label(octets);
+
+        assert_eq!(label, "a\\.b\\\\\\032\\255_-");
+        assert_eq!(label_octets(&label), octets);
+    }
+
+    #[test]
+    fn it_splits_labels_at_unescaped_dots() {
+        let name = DomainName::from("a\\.b.c\\\\.d");
+
+        let labels: Vec<_> = name.labels().collect();
+
+        assert_eq!(labels, vec!["a\\.b", "c\\\\", "d"]);
+    }
+
+    #[test]
+    fn it_compares_names_case_insensitively() {
+        let name = DomainName::from("Test.DYN.example.com");
+
+        assert_eq!(name, DomainName::from("test.dyn.EXAMPLE.com"));
+        assert_ne!(name, DomainName::from("test.dyn.example.org"));
+        assert_eq!(name.to_string(), "Test.DYN.example.com");
+    }
+
+    #[test]
+    fn it_hashes_names_case_insensitively() {
+        let mut names = std::collections::HashSet::new();
+        names.insert(DomainName::from("test"));
+
+        assert!(names.contains(&DomainName::from("TeSt")));
+    }
+
+    #[test]
+    fn it_ignores_trailing_dot() {
+        let name = DomainName::from("dyn.example.com.");
+
+        assert_eq!(name, DomainName::from("dyn.example.com"));
+        assert_ne!(name, DomainName::from("dyn.example"));
+        assert_ne!(DomainName::from(r"test\.dyn"), DomainName::from("test.dyn"));
+
+        let mut names = std::collections::HashSet::new();
+        names.insert(DomainName::from("test."));
+        assert!(names.contains(&DomainName::from("test")));
+    }
+
+    #[test]
+    fn it_strips_suffix_case_insensitively() {
+        let name = DomainName::from("Test.DYN.example.com");
+
+        let sub_domain = name.strip_suffix(&DomainName::from("dyn.example.COM"));
+
+        assert_eq!(sub_domain.unwrap().to_string(), "Test");
+        assert_eq!(
+            name.strip_suffix(&DomainName::from("st.dyn.example.com")),
+            None
+        );
+    }
+
+    #[test]
+    fn it_strips_suffix_at_label_boundaries_only() {
+        let name = DomainName::from(r"x\.dyn.example.com");
+
+        assert_eq!(
+            name.strip_suffix(&DomainName::from("dyn.example.com")),
+            None
+        );
+        assert!(!name.is_subdomain_of(&DomainName::from("dyn.example.com")));
+        assert_eq!(
+            name.strip_suffix(&DomainName::from("example.com"))
+                .unwrap()
+                .to_string(),
+            r"x\.dyn"
+        );
+    }
+
+    #[test]
+    fn it_returns_parent() {
+        let name = DomainName::from("_acme-challenge.a\\.b.dyn");
+
+        let parent = name.parent().unwrap();
+
+        assert_eq!(parent.to_string(), "a\\.b.dyn");
+        assert_eq!(parent.parent().unwrap().to_string(), "dyn");
+        assert_eq!(DomainName::from("dyn").parent(), None);
+    }
+
+    #[test]
+    fn it_builds_reverse_names() {
+        let ipv4 = reverse_name(IpAddr::from([192, 0, 2, 10]));
+        let ipv6 = reverse_name("2001:db8::5678".parse().unwrap());
+
+        assert_eq!(ipv4.to_string(), "10.2.0.192.in-addr.arpa");
+        assert_eq!(
+            ipv6.to_string(),
+            "8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn it_validates_hostnames() {
+        assert!(DomainName::from("test").is_valid_hostname());
+        assert!(DomainName::from("3com.dyn.example.com").is_valid_hostname());
+        assert!(DomainName::from("test-1.dyn").is_valid_hostname());
+
+        assert!(!DomainName::from("").is_valid_hostname());
+        assert!(!DomainName::from("-test").is_valid_hostname());
+        assert!(!DomainName::from("test-.dyn").is_valid_hostname());
+        assert!(!DomainName::from("_acme-challenge.test").is_valid_hostname());
+        assert!(!DomainName::from("te st").is_valid_hostname());
+        assert!(!DomainName::from("a".repeat(64).as_str()).is_valid_hostname());
+    }
+
+    #[test]
+    fn it_validates_owner_names() {
+        assert!(DomainName::from("test").is_valid_owner_name());
+        assert!(DomainName::from("_acme-challenge.test").is_valid_owner_name());
+        assert!(DomainName::from("_sip._tcp.test").is_valid_owner_name());
+        assert!(DomainName::from("_dmarc").is_valid_owner_name());
+        assert!(DomainName::from("*.home").is_valid_owner_name());
+        assert!(DomainName::from("*").is_valid_owner_name());
+
+        assert!(!DomainName::from("").is_valid_owner_name());
+        assert!(!DomainName::from("_.test").is_valid_owner_name());
+        assert!(!DomainName::from("test._tcp").is_valid_owner_name());
+        assert!(!DomainName::from("_-sip.test").is_valid_owner_name());
+        assert!(!DomainName::from("home.*").is_valid_owner_name());
+    }
+}
@@ -3,7 +3,7 @@ use crate::{
     parser,
 };
 
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
 
 use std::io;
@@ -35,9 +35,6 @@ impl Decoder for Codec {
             // not a enough data for a valid header
             return Ok(None);
         }
-        // TODO DNS messages are restricted to 512 bytes, if this limit is
-        // exceeded the messages should be truncated and the TC bit must be
-        // set in the header
 
         let msg = match parser::dns_query(buf) {
             Ok(query) => {
@@ -49,6 +46,11 @@ impl Decoder for Codec {
             }
             Err(err) => match err {
                 parser::Error::Incomplete => return Ok(None),
+                parser::Error::Response => {
+                    log::debug!("Dropping DNS response.");
+                    buf.clear();
+                    return Ok(None);
+                }
                 parser::Error::Parser => {
                     buf.clear();
                     return Err(Error::Decoding);
@@ -58,6 +60,31 @@ impl Decoder for Codec {
 
         Ok(Some(msg))
     }
+
+    /// Called with a complete message, e.g. a whole datagram, so data that
+    /// is still missing makes the message malformed.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(msg) = self.decode(buf)? {
+            return Ok(Some(msg));
+        }
+
+        if buf.is_empty() {
+            return Ok(None);
+        }
+
+        // without a header we don't even know whom to answer
+        let header = parser::dns_header(buf).map(|(_, header)| header).ok();
+        buf.clear();
+
+        match header {
+            Some(header) if header.qr == dns::Qr::Query => {
+                Ok(Some(dns::Request::Malformed(header)))
+            }
+            // responses are never answered, not even malformed ones
+            Some(_) => Ok(None),
+            None => Err(Error::Decoding),
+        }
+    }
 }
 
 impl Encoder<dns::Response> for Codec {
@@ -65,13 +92,72 @@ impl Encoder<dns::Response> for Codec {
 
     fn encode(&mut self, response: dns::Response, buf: &mut BytesMut) -> Result<(), io::Error> {
         // TODO: remove double allocation
-        let data = response.as_u8();
+        let data = response.as_truncated_u8();
         buf.reserve(data.len());
         buf.put(data.as_ref());
         Ok(())
     }
 }
 
+/// DNS over TCP prefixes every message with its length as a two byte field,
+/// see [RFC 1035](https://tools.ietf.org/html/rfc1035#section-4.2.2).
+/// This codec handles the prefix and hands the actual message to [`Codec`].
+#[derive(Default)]
+pub struct TcpCodec {
+    inner: Codec,
+}
+
+const LENGTH_PREFIX_LEN: usize = 2;
+
+impl Decoder for TcpCodec {
+    type Item = dns::Request;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // dropped responses yield nothing, so we continue with the next
+        // message until a request is found or the buffer runs dry
+        loop {
+            if buf.len() < LENGTH_PREFIX_LEN {
+                return Ok(None);
+            }
+
+            let msg_len = usize::from(u16::from_be_bytes([buf[0], buf[1]]));
+            let frame_len = LENGTH_PREFIX_LEN + msg_len;
+            if buf.len() < frame_len {
+                // the rest of the message has not arrived yet
+                buf.reserve(frame_len - buf.len());
+                return Ok(None);
+            }
+
+            buf.advance(LENGTH_PREFIX_LEN);
+            let mut msg = buf.split_to(msg_len);
+            if msg.is_empty() {
+                return Err(Error::Decoding);
+            }
+
+            // the whole message is available, so anything the inner codec
+            // considers incomplete is actually malformed
+            if let Some(request) = self.inner.decode_eof(&mut msg)? {
+                return Ok(Some(request));
+            }
+        }
+    }
+}
+
+impl Encoder<dns::Response> for TcpCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, response: dns::Response, buf: &mut BytesMut) -> Result<(), io::Error> {
+        let data = response.as_u8();
+        let msg_len = u16::try_from(data.len()).map_err(io::Error::other)?;
+
+        buf.reserve(LENGTH_PREFIX_LEN + data.len());
+        buf.put_u16(msg_len);
+        buf.put(data.as_ref());
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,6 +169,7 @@ mod tests {
 
         let header = dns::RawHeader {
             id: 1234,
+            qr: dns::Qr::Query,
             opcode: dns::OpCode::StandardQuery,
             truncated: false,
             authoritative_answer: false,
@@ -109,6 +196,7 @@ mod tests {
 
         let header = dns::RawHeader {
             id: 1234,
+            qr: dns::Qr::Query,
             opcode: dns::OpCode::StandardQuery,
             truncated: false,
             authoritative_answer: false,
@@ -151,6 +239,7 @@ mod tests {
 
         let header = dns::RawHeader {
             id: 1234,
+            qr: dns::Qr::Query,
             opcode: dns::OpCode::StandardQuery,
             truncated: false,
             authoritative_answer: false,
@@ -189,4 +278,222 @@ mod tests {
         let result = codec.decode(&mut buf).unwrap();
         assert!(matches!(result, Some(dns::Request::StandardQuery(_))));
     }
+
+    fn raw_query(id: u16) -> Vec<u8> {
+        let header = dns::RawHeader {
+            id,
+            qr: dns::Qr::Query,
+            opcode: dns::OpCode::StandardQuery,
+            truncated: false,
+            authoritative_answer: false,
+            recursion_desired: false,
+            recursion_available: false,
+            response_code: dns::response::Rcode::NoError,
+            qd_count: 1,
+            an_count: 0,
+            ns_count: 0,
+            ar_count: 0,
+        };
+
+        let question = dns::Question {
+            labels: ["example", "test", "com"]
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>(),
+            name: "example.test.com".to_string(),
+            query_type: dns::QueryType::A,
+            query_class: dns::QueryClass::IN,
+        };
+
+        let mut raw_query: Vec<u8> = header.into();
+        let raw_qustion: Vec<u8> = question.into();
+        raw_query.extend(raw_qustion);
+
+        raw_query
+    }
+
+    fn length_prefixed(msg: &[u8]) -> Vec<u8> {
+        let mut raw = vec![];
+        raw.put_u16(msg.len() as u16);
+        raw.put(msg);
+        raw
+    }
+
+    #[test]
+    fn tcp_slow_sender_sends_length_prefix_in_parts() {
+        let mut codec = TcpCodec::default();
+        let mut buf = BytesMut::new();
+
+        let raw = length_prefixed(&raw_query(1234));
+
+        buf.put(&raw[0..1]);
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+
+        buf.put(&raw[1..20]);
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+
+        buf.put(&raw[20..]);
+        let result = codec.decode(&mut buf).unwrap();
+        assert!(matches!(result, Some(dns::Request::StandardQuery(_))));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn tcp_multiple_queries_in_one_buffer() {
+        let mut codec = TcpCodec::default();
+        let mut buf = BytesMut::new();
+
+        buf.put(length_prefixed(&raw_query(1)).as_ref());
+        buf.put(length_prefixed(&raw_query(2)).as_ref());
+
+        let Some(dns::Request::StandardQuery(first)) = codec.decode(&mut buf).unwrap() else {
+            panic!("expected a standard query");
+        };
+        let Some(dns::Request::StandardQuery(second)) = codec.decode(&mut buf).unwrap() else {
+            panic!("expected a standard query");
+        };
+
+        assert_eq!(first.header.id, 1);
+        assert_eq!(second.header.id, 2);
+        assert_eq!(None, codec.decode(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn tcp_incomplete_message_is_an_error() {
+        let mut codec = TcpCodec::default();
+        let mut buf = BytesMut::new();
+
+        let query = raw_query(1234);
+        buf.put(length_prefixed(&query[0..11]).as_ref());
+
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn incomplete_datagram_is_malformed() {
+        let mut codec = Codec;
+        let mut buf = BytesMut::new();
+
+        let query = raw_query(1234);
+        buf.put(&query[0..20]);
+
+        let result = codec.decode_eof(&mut buf).unwrap();
+
+        assert!(matches!(result, Some(dns::Request::Malformed(header)) if header.id == 1234));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn datagram_without_header_is_an_error() {
+        let mut codec = Codec;
+        let mut buf = BytesMut::new();
+
+        let query = raw_query(1234);
+        buf.put(&query[0..11]);
+
+        assert!(codec.decode_eof(&mut buf).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn tcp_incomplete_question_is_malformed() {
+        let mut codec = TcpCodec::default();
+        let mut buf = BytesMut::new();
+
+        let query = raw_query(1234);
+        buf.put(length_prefixed(&query[0..20]).as_ref());
+
+        let result = codec.decode(&mut buf).unwrap();
+        assert!(matches!(result, Some(dns::Request::Malformed(_))));
+    }
+
+    fn raw_formerr_response(id: u16) -> Vec<u8> {
+        let header = dns::RawHeader {
+            id,
+            qr: dns::Qr::Response,
+            opcode: dns::OpCode::StandardQuery,
+            truncated: false,
+            authoritative_answer: false,
+            recursion_desired: false,
+            recursion_available: false,
+            response_code: dns::response::Rcode::FormatError,
+            qd_count: 0,
+            an_count: 0,
+            ns_count: 0,
+            ar_count: 0,
+        };
+
+        header.into()
+    }
+
+    #[test]
+    fn responses_are_dropped() {
+        let mut codec = Codec;
+        let mut buf = BytesMut::new();
+
+        // e.g. our own FORMERR response reflected back to us
+        buf.put(raw_formerr_response(1234).as_ref());
+
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn incomplete_responses_are_dropped() {
+        let mut codec = Codec;
+        let mut buf = BytesMut::new();
+
+        let mut response = raw_query(1234);
+        response[2] |= 0b1000_0000;
+        buf.put(&response[0..20]);
+
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn tcp_skips_responses() {
+        let mut codec = TcpCodec::default();
+        let mut buf = BytesMut::new();
+
+        // enough responses to fill a whole read, which must neither be
+        // answered nor exhaust the stack
+        for id in 0..5000 {
+            buf.put(length_prefixed(&raw_formerr_response(id)).as_ref());
+        }
+        buf.put(length_prefixed(&raw_query(5000)).as_ref());
+
+        let result = codec.decode(&mut buf).unwrap();
+        assert!(
+            matches!(result, Some(dns::Request::StandardQuery(query)) if query.header.id == 5000)
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn tcp_encodes_length_prefix() {
+        let mut codec = TcpCodec::default();
+        let mut buf = BytesMut::new();
+
+        let header = dns::RawHeader {
+            id: 1234,
+            qr: dns::Qr::Response,
+            opcode: dns::OpCode::StandardQuery,
+            truncated: false,
+            authoritative_answer: true,
+            recursion_desired: false,
+            recursion_available: false,
+            response_code: dns::response::Rcode::NotImplemented,
+            qd_count: 0,
+            an_count: 0,
+            ns_count: 0,
+            ar_count: 0,
+        };
+        let response = dns::Response::NotImplemented(dns::response::NotImplemented { header });
+
+        codec.encode(response, &mut buf).unwrap();
+
+        assert_eq!(&buf[0..2], &[0, 12]);
+        assert_eq!(buf.len(), 14);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::dns::{Question, RawHeader};
+use crate::dns::{Edns, Question, RawHeader};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Request {
@@ -15,12 +15,16 @@ pub enum Request {
     /// layout structure for the rest of the message, there is no
     /// requirement that anything other than the DNS header match.
     Unsupported(RawHeader),
+    /// A query that could only be parsed up to its header, which has to be
+    /// answered with FORMERR.
+    Malformed(RawHeader),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct StandardQuery {
     pub header: Header,
     pub question: Question,
+    pub edns: Option<Edns>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
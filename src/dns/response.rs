@@ -1,16 +1,38 @@
-use crate::dns::{OpCode, Question, RawHeader, ResourceRecord};
+use crate::dns::{Edns, MessageWriter, OpCode, Qr, Question, RawHeader, ResourceRecord};
 
 #[derive(Debug)]
 pub enum Response {
     StandardQuery(StandardQuery),
     NotImplemented(NotImplemented),
+    FormatError(FormatError),
 }
 
+/// Maximum size of a DNS message sent over UDP if the client doesn't
+/// announce a larger size via EDNS, see
+/// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-4.2.1).
+pub const MAX_UDP_SIZE: u16 = 512;
+
+const HEADER_LEN: usize = 12;
+
 impl Response {
     pub fn as_u8(self) -> Vec<u8> {
         match self {
             Response::StandardQuery(r) => r.as_u8(),
             Response::NotImplemented(r) => r.as_u8(),
+            Response::FormatError(r) => r.as_u8(),
+        }
+    }
+
+    /// Encodes the response so that it fits into a single UDP datagram
+    /// the client is able to receive.
+    pub fn as_truncated_u8(self) -> Vec<u8> {
+        match self {
+            Response::StandardQuery(r) => {
+                let max_size = usize::from(r.udp_payload_size);
+                r.encode(max_size)
+            }
+            Response::NotImplemented(r) => r.as_u8(),
+            Response::FormatError(r) => r.as_u8(),
         }
     }
 }
@@ -21,40 +43,99 @@ pub struct StandardQuery {
     pub header: Header,
     pub question: Question,
     pub answer: Vec<ResourceRecord>,
-    // authority: Vec<DnsResourceRecord>,
-    // additional: Vec<DnsResourceRecord>
+    pub authority: Vec<ResourceRecord>,
+    pub additional: Vec<ResourceRecord>,
+    /// OPT record, which is only sent if the request contained one
+    pub edns: Option<Edns>,
+    /// Maximum message size the client is able to receive over UDP
+    pub udp_payload_size: u16,
 }
 
 impl StandardQuery {
     pub fn as_u8(self) -> Vec<u8> {
+        self.encode(usize::from(u16::MAX))
+    }
+
+    /// Encodes the message, dropping whole resource records that would
+    /// exceed `max_size` and setting the TC bit in that case, see
+    /// [RFC 2181](https://www.rfc-editor.org/rfc/rfc2181#section-9).
+    fn encode(self, max_size: usize) -> Vec<u8> {
+        let raw_edns: Option<Vec<u8>> = self.edns.map(|edns| {
+            // the upper eight bits of the 12 bit RCODE are carried by the
+            // OPT record
+            let edns = Edns {
+                extended_rcode: u8::from(self.header.response_code) >> 4,
+                ..edns
+            };
+            edns.into()
+        });
+        // the OPT record must not be dropped, so we reserve space for it
+        let max_size = max_size - raw_edns.as_ref().map_or(0, Vec::len);
+
+        let mut writer = MessageWriter::new();
+        // the header is written last, when all counts are known
+        writer.put_slice(&[0; HEADER_LEN]);
+        writer.write_question(&self.question);
+
+        let (an_count, mut truncated) = write_records(&mut writer, &self.answer, max_size);
+        let mut ns_count = 0;
+        if !truncated {
+            (ns_count, truncated) = write_records(&mut writer, &self.authority, max_size);
+        }
+        // records that don't fit into the additional section don't set the
+        // TC bit, because the client doesn't need them to use the answer
+        let mut ar_count = 0;
+        if !truncated {
+            (ar_count, _) = write_records(&mut writer, &self.additional, max_size);
+        }
+
+        if let Some(raw_edns) = &raw_edns {
+            writer.put_slice(raw_edns);
+        }
+
         let raw_header = RawHeader {
             id: self.header.id,
+            qr: Qr::Response,
             opcode: OpCode::StandardQuery,
-            truncated: self.header.truncated,
-            // we only act as authoritative server
-            authoritative_answer: true,
+            truncated: self.header.truncated || truncated,
+            authoritative_answer: self.header.authoritative_answer,
             recursion_desired: self.header.recursion_desired,
             // we don't support recursion
             recursion_available: false,
-            response_code: Rcode::NoError,
+            response_code: self.header.response_code,
             qd_count: self.header.qd_count,
-            an_count: self.answer.len() as u16,
-            ns_count: 0,
-            ar_count: 0,
+            an_count,
+            ns_count,
+            ar_count: ar_count + raw_edns.is_some() as u16,
         };
-        let mut raw_message: Vec<u8> = raw_header.into();
-        let mut raw_question: Vec<u8> = self.question.into();
 
-        raw_message.append(&mut raw_question);
+        let mut raw_message: Vec<u8> = writer.into();
+        raw_message[..HEADER_LEN].copy_from_slice(&Vec::<u8>::from(raw_header));
 
-        for rr in self.answer {
-            let mut raw_resource_record: Vec<u8> = rr.into();
+        raw_message
+    }
+}
 
-            raw_message.append(&mut raw_resource_record);
+/// Writes as many records as fit into `max_size`. Returns the number of
+/// written records and whether some of them had to be dropped.
+fn write_records(
+    writer: &mut MessageWriter,
+    records: &[ResourceRecord],
+    max_size: usize,
+) -> (u16, bool) {
+    let mut count = 0;
+    for rr in records {
+        let len = writer.len();
+        writer.write_record(rr);
+        if writer.len() > max_size {
+            writer.truncate(len);
+            return (count, true);
         }
 
-        raw_message
+        count += 1;
     }
+
+    (count, false)
 }
 
 // impl From<&StandardQuery> for Vec<u8> {
@@ -90,6 +171,19 @@ impl NotImplemented {
     }
 }
 
+/// Response to a malformed request, which only consists of the header,
+/// because the rest of the request is unknown.
+#[derive(Debug)]
+pub struct FormatError {
+    pub header: RawHeader,
+}
+
+impl FormatError {
+    pub fn as_u8(self) -> Vec<u8> {
+        self.header.into()
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Rcode {
     NoError,
@@ -98,6 +192,8 @@ pub enum Rcode {
     NameError,
     NotImplemented,
     Refused,
+    /// Extended RCODE, see [RFC 6891](https://www.rfc-editor.org/rfc/rfc6891#section-9)
+    BadVersion,
 }
 
 impl From<Rcode> for u8 {
@@ -109,6 +205,7 @@ impl From<Rcode> for u8 {
             Rcode::NameError => 3,
             Rcode::NotImplemented => 4,
             Rcode::Refused => 5,
+            Rcode::BadVersion => 16,
         }
     }
 }
@@ -121,7 +218,170 @@ impl From<u8> for Rcode {
             2 => Rcode::ServerFailure,
             3 => Rcode::NameError,
             4 => Rcode::NotImplemented,
+            16 => Rcode::BadVersion,
             _ => Rcode::Refused,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+    use crate::dns::{DomainName, Name, QueryClass, QueryType, StartOfAuthority};
+
+    fn response(answers: u16, edns: Option<Edns>, udp_payload_size: u16) -> StandardQuery {
+        let labels: Vec<String> = ["host", "dyn", "example", "com"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let answer = (0..answers)
+            .map(|i| ResourceRecord::AAAA {
+                name: Name::Labels(labels.clone()),
+                ttl: 15,
+                addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, i),
+            })
+            .collect();
+
+        StandardQuery {
+            header: Header {
+                id: 1234,
+                truncated: false,
+                authoritative_answer: true,
+                recursion_desired: false,
+                recursion_available: false,
+                response_code: Rcode::NoError,
+                qd_count: 1,
+            },
+            question: Question {
+                name: labels.join("."),
+                labels,
+                query_type: QueryType::AAAA,
+                query_class: QueryClass::IN,
+            },
+            answer,
+            authority: vec![],
+            additional: vec![],
+            edns,
+            udp_payload_size,
+        }
+    }
+
+    fn parse_header(raw: &[u8]) -> (bool, u16, u16) {
+        let truncated = raw[2] & 0b0000_0010 != 0;
+        let an_count = u16::from_be_bytes([raw[6], raw[7]]);
+        let ar_count = u16::from_be_bytes([raw[10], raw[11]]);
+
+        (truncated, an_count, ar_count)
+    }
+
+    #[test]
+    fn small_response_is_not_truncated() {
+        let raw = Response::StandardQuery(response(2, None, MAX_UDP_SIZE)).as_truncated_u8();
+
+        assert_eq!(parse_header(&raw), (false, 2, 0));
+    }
+
+    #[test]
+    fn large_response_is_truncated_to_512_bytes() {
+        let raw = Response::StandardQuery(response(20, None, MAX_UDP_SIZE)).as_truncated_u8();
+
+        let (truncated, an_count, _) = parse_header(&raw);
+        assert!(truncated);
+        assert!(raw.len() <= usize::from(MAX_UDP_SIZE));
+        // question: 22 bytes name + 4 bytes; answer: 2 byte pointer + 10 +
+        // 16 bytes
+        assert_eq!(an_count, ((512 - 12 - 26) / 28) as u16);
+        assert_eq!(raw.len(), 12 + 26 + usize::from(an_count) * 28);
+    }
+
+    #[test]
+    fn truncated_response_keeps_opt_record() {
+        let edns = Edns {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![],
+        };
+        let raw = Response::StandardQuery(response(60, Some(edns), 1232)).as_truncated_u8();
+
+        let (truncated, _, ar_count) = parse_header(&raw);
+        assert!(truncated);
+        assert!(raw.len() <= 1232);
+        assert_eq!(ar_count, 1);
+        assert_eq!(&raw[raw.len() - 11..raw.len() - 8], &[0, 0, 41]);
+    }
+
+    #[test]
+    fn tcp_response_is_not_truncated() {
+        let raw = Response::StandardQuery(response(40, None, MAX_UDP_SIZE)).as_u8();
+
+        let (truncated, an_count, _) = parse_header(&raw);
+        assert!(!truncated);
+        assert_eq!(an_count, 40);
+    }
+
+    #[test]
+    fn answer_names_are_compressed() {
+        let raw = Response::StandardQuery(response(1, None, MAX_UDP_SIZE)).as_u8();
+
+        // the owner name points to the name in the question section
+        assert_eq!(&raw[12 + 26..12 + 28], &[0b11000000, 12]);
+    }
+
+    #[test]
+    fn authority_section_is_written() {
+        let soa = StartOfAuthority {
+            mname: DomainName::from("dyn.example.com"),
+            rname: DomainName::from("postmaster.example.com"),
+            serial: 1,
+            refresh: 2,
+            retry: 3,
+            expire: 4,
+            minimum: 5,
+        };
+        let msg = StandardQuery {
+            authority: vec![ResourceRecord::SOA { ttl: 5, soa }],
+            ..response(0, None, MAX_UDP_SIZE)
+        };
+
+        let raw = Response::StandardQuery(msg).as_truncated_u8();
+
+        let ns_count = u16::from_be_bytes([raw[8], raw[9]]);
+        assert_eq!(ns_count, 1);
+        // owner name is compressed to the `dyn.example.com` suffix of the
+        // question name, followed by type SOA
+        assert_eq!(&raw[12 + 26..12 + 30], &[0b11000000, 17, 0, 6]);
+    }
+
+    #[test]
+    fn aa_bit_is_taken_from_header() {
+        let mut msg = response(0, None, MAX_UDP_SIZE);
+        msg.header.authoritative_answer = false;
+
+        let raw = Response::StandardQuery(msg).as_u8();
+
+        assert_eq!(raw[2] & 0b0000_0100, 0);
+        let raw = Response::StandardQuery(response(0, None, MAX_UDP_SIZE)).as_u8();
+        assert_ne!(raw[2] & 0b0000_0100, 0);
+    }
+
+    #[test]
+    fn dropped_additional_records_do_not_set_tc_bit() {
+        let additional = response(20, None, MAX_UDP_SIZE).answer;
+        let msg = StandardQuery {
+            additional,
+            ..response(1, None, MAX_UDP_SIZE)
+        };
+
+        let raw = Response::StandardQuery(msg).as_truncated_u8();
+
+        let (truncated, an_count, ar_count) = parse_header(&raw);
+        assert!(!truncated);
+        assert_eq!(an_count, 1);
+        assert_eq!(ar_count, ((512 - 12 - 26) / 28 - 1) as u16);
+    }
+}
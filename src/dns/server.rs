@@ -1,51 +1,230 @@
-use std::net::{IpAddr, SocketAddr};
+use std::{collections::HashMap, io, net::SocketAddr, sync::Arc, time::Duration};
 
 use futures::{SinkExt, StreamExt};
-use tokio::net::UdpSocket;
-use tokio_util::udp::UdpFramed;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+use tokio_util::{codec::Framed, udp::UdpFramed};
 
 use crate::{
-    SharedStorage,
+    SharedStorage, Storage,
     dns::{self, DomainName, ResourceRecord, request, response},
+    storage::{CaaProperty, SubDomainEntry, Ttls},
 };
 
-pub struct Server {
+/// Time after which an idle TCP connection gets closed by the server, see
+/// [RFC 7766](https://www.rfc-editor.org/rfc/rfc7766#section-6.2.3).
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time a TCP client gets to read a response before the server gives up on
+/// the connection, so that a client which never reads can't hold on to it.
+const TCP_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of TCP connections served at the same time, across all
+/// listeners. Further clients wait in the backlog of the listener.
+const MAX_TCP_CONNECTIONS: usize = 256;
+
+/// Pause after a failed accept, e.g. when running out of file descriptors,
+/// so that the error doesn't repeat in a busy loop.
+const TCP_ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// UDP payload size advertised in our OPT records, as recommended by the
+/// [DNS flag day 2020](https://www.dnsflagday.net/2020/).
+const EDNS_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// The only EDNS version we support, see
+/// [RFC 6891](https://www.rfc-editor.org/rfc/rfc6891#section-6.1.3).
+const EDNS_VERSION: u8 = 0;
+
+/// TTL of records without a configured TTL.
+const DEFAULT_TTL: u32 = 15;
+
+/// Limits the number of CNAME records followed for a single query.
+const MAX_CNAME_CHAIN_LEN: usize = 8;
+
+/// Defines how queries of type ANY are answered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnyQuery {
+    /// A single representative RRset as suggested by
+    /// [RFC 8482](https://www.rfc-editor.org/rfc/rfc8482#section-4.1).
+    #[default]
+    Minimal,
+    /// Both A and AAAA records of a host, other names are answered like in
+    /// the minimal mode.
+    Addresses,
+}
+
+/// A zone the server is authoritative for.
+#[derive(Debug, Clone)]
+pub struct Zone {
     pub soa: dns::StartOfAuthority,
+    pub name_servers: Vec<DomainName>,
+    /// CAA records of the zone apex
+    pub caa: Vec<CaaProperty>,
+    /// TTLs of all records, unless overridden by their entry
+    pub ttl: Ttls,
+}
+
+pub struct Server {
+    /// All zones, including the reverse zones, keyed by their apex
+    pub zones: HashMap<DomainName, Zone>,
+    pub any_query: AnyQuery,
     pub storage: SharedStorage,
-    pub listen_addr: IpAddr,
-    pub listen_port: u16,
 }
 
 impl Server {
-    pub async fn run(self) {
-        let addr = SocketAddr::from((self.listen_addr, self.listen_port));
-        let udp_socket = UdpSocket::bind(&addr).await.unwrap();
-        // TODO: drop privileges
+    /// Answers queries on all given sockets, each in its own task, until an
+    /// I/O error occurs that prevents one of them from being served.
+    pub async fn run(
+        self,
+        udp_sockets: Vec<UdpSocket>,
+        tcp_listeners: Vec<TcpListener>,
+    ) -> io::Result<()> {
+        let server = Arc::new(self);
+        let tcp_connections = Arc::new(Semaphore::new(MAX_TCP_CONNECTIONS));
+
+        let mut tasks = JoinSet::new();
+        for udp_socket in udp_sockets {
+            tasks.spawn(Arc::clone(&server).serve_udp(udp_socket));
+        }
+        for tcp_listener in tcp_listeners {
+            tasks.spawn(Arc::clone(&server).serve_tcp(
+                tcp_listener,
+                Arc::clone(&tcp_connections),
+                TCP_SEND_TIMEOUT,
+            ));
+        }
 
-        let mut dns_stream = UdpFramed::new(udp_socket, dns::Codec);
+        while let Some(res) = tasks.join_next().await {
+            res.map_err(io::Error::other)??;
+        }
 
-        log::info!(
-            "DNS server now listening on: {ip}:{port}",
-            ip = self.listen_addr,
-            port = self.listen_port
-        );
+        Ok(())
+    }
+
+    async fn serve_udp(self: Arc<Self>, udp_socket: UdpSocket) -> io::Result<()> {
+        let addr = udp_socket.local_addr()?;
+        let udp_socket = Arc::new(udp_socket);
+        let mut dns_stream = UdpFramed::new(Arc::clone(&udp_socket), dns::Codec);
+
+        log::info!("DNS server now listening on: {addr} (UDP)");
 
         while let Some(res) = dns_stream.next().await {
             let (request, addr) = match res {
                 Ok((query, addr)) => (query, addr),
                 Err(err) => {
-                    // TODO we probably should stop the server at this point
+                    // a single bad datagram must not stop the server
                     log::error!("{err}");
                     continue;
                 }
             };
 
-            // TODO: spawn a task for responding
+            // queries are answered concurrently, so a burst of them doesn't
+            // delay the next datagram
+            tokio::spawn(Arc::clone(&self).handle_datagram(Arc::clone(&udp_socket), request, addr));
+        }
+
+        Ok(())
+    }
+
+    async fn handle_datagram(
+        self: Arc<Self>,
+        udp_socket: Arc<UdpSocket>,
+        request: dns::Request,
+        addr: SocketAddr,
+    ) {
+        log::debug!("DNS message received: {:?}", request);
+        let response = self.creat_response(request);
+
+        log::debug!("Sending DNS response: {:?}", response);
+        if let Err(err) = udp_socket.send_to(&response.as_truncated_u8(), addr).await {
+            // e.g. the client is unreachable, which only affects itself
+            log::error!("failed to send DNS response to {addr}: {err}");
+        }
+    }
+
+    /// Accepts connections as long as one of the `connections` permits is
+    /// available.
+    async fn serve_tcp(
+        self: Arc<Self>,
+        listener: TcpListener,
+        connections: Arc<Semaphore>,
+        send_timeout: Duration,
+    ) -> io::Result<()> {
+        let addr = listener.local_addr()?;
+
+        log::info!("DNS server now listening on: {addr} (TCP)");
+
+        loop {
+            let permit = Arc::clone(&connections)
+                .acquire_owned()
+                .await
+                .map_err(io::Error::other)?;
+
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    log::error!("failed to accept TCP connection: {err}");
+                    tokio::time::sleep(TCP_ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            tokio::spawn(Arc::clone(&self).handle_tcp_connection(
+                stream,
+                peer,
+                permit,
+                send_timeout,
+            ));
+        }
+    }
+
+    /// Answers queries on a single TCP connection until the client closes
+    /// it, sends garbage, stays idle for longer than [`TCP_IDLE_TIMEOUT`] or
+    /// doesn't read a response within `send_timeout`.
+    /// The `permit` is released once the connection is closed.
+    async fn handle_tcp_connection(
+        self: Arc<Self>,
+        stream: TcpStream,
+        peer: SocketAddr,
+        _permit: OwnedSemaphorePermit,
+        send_timeout: Duration,
+    ) {
+        let mut dns_stream = Framed::new(stream, dns::TcpCodec::default());
+
+        loop {
+            let request = match tokio::time::timeout(TCP_IDLE_TIMEOUT, dns_stream.next()).await {
+                Ok(Some(Ok(request))) => request,
+                Ok(Some(Err(err))) => {
+                    log::debug!("closing TCP connection to {peer}: {err}");
+                    break;
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    log::debug!("closing idle TCP connection to {peer}");
+                    break;
+                }
+            };
+
             log::debug!("DNS message received: {:?}", request);
             let response = self.creat_response(request);
 
             log::debug!("Sending DNS response: {:?}", response);
-            dns_stream.send((response, addr)).await.unwrap();
+            match tokio::time::timeout(send_timeout, dns_stream.send(response)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => {
+                    log::error!("failed to send DNS response to {peer}: {err}");
+                    break;
+                }
+                Err(_) => {
+                    log::debug!("closing TCP connection to {peer} that doesn't read");
+                    break;
+                }
+            }
         }
     }
 
@@ -56,21 +235,14 @@ impl Server {
                 dns::Response::StandardQuery(response)
             }
             dns::Request::Unsupported(header) => unsupported_response(header),
+            dns::Request::Malformed(header) => format_error_response(header),
         }
     }
 
     pub fn standard_query(&self, query: request::StandardQuery) -> response::StandardQuery {
-        let storage = match self.storage.lock() {
-            Ok(storage) => storage,
-            Err(err) => {
-                // TODO return internal server error
-                log::error!("failed to lock storage: {err}");
-                panic!();
-            }
-        };
         let mut header = response::Header {
             id: query.header.id,
-            truncated: query.header.truncated,
+            truncated: false,
             authoritative_answer: true,
             recursion_desired: query.header.recursion_desired,
             recursion_available: false,
@@ -78,66 +250,441 @@ impl Server {
             qd_count: query.header.qd_count,
         };
 
+        let edns = query.edns.as_ref().map(|edns| dns::Edns {
+            udp_payload_size: EDNS_UDP_PAYLOAD_SIZE,
+            extended_rcode: 0,
+            version: EDNS_VERSION,
+            dnssec_ok: edns.dnssec_ok,
+            options: vec![],
+        });
+
+        // sizes below 512 bytes must be treated as 512 bytes and we never
+        // send more than we advertise ourselves
+        let udp_payload_size = query.edns.as_ref().map_or(response::MAX_UDP_SIZE, |edns| {
+            edns.udp_payload_size
+                .clamp(response::MAX_UDP_SIZE, EDNS_UDP_PAYLOAD_SIZE)
+        });
+
+        let empty_response = |header| response::StandardQuery {
+            header,
+            question: query.question.clone(),
+            answer: vec![],
+            authority: vec![],
+            additional: vec![],
+            edns: edns.clone(),
+            udp_payload_size,
+        };
+
+        if query
+            .edns
+            .as_ref()
+            .is_some_and(|edns| edns.version > EDNS_VERSION)
+        {
+            header.response_code = response::Rcode::BadVersion;
+            return empty_response(header);
+        }
+
+        // our zone only contains records of class IN
         if !matches!(
-            query.question.query_type,
-            dns::QueryType::A | dns::QueryType::AAAA | dns::QueryType::SOA
+            query.question.query_class,
+            dns::QueryClass::IN | dns::QueryClass::ANY
         ) {
-            return response::StandardQuery {
-                header,
-                question: query.question,
-                answer: vec![],
-            };
+            header.authoritative_answer = false;
+            header.response_code = response::Rcode::Refused;
+            return empty_response(header);
         }
 
+        // we are not authoritative for names outside of our zones and don't
+        // offer recursion, so we must not claim that those names don't exist
         let domain_name = DomainName::from(query.question.name.clone());
-        let record = storage.get(&domain_name);
-        let answer = match query.question.query_type {
-            dns::QueryType::A => record.and_then(|r| r.ipv4).map(|ip| {
-                vec![ResourceRecord::A {
-                    // TODO: use compression, e.g. `Name::Pointer`
-                    name: dns::Name::Labels(query.question.labels.clone()),
-                    ttl: 15,
-                    addr: ip,
-                }]
-            }),
-            dns::QueryType::AAAA => record.and_then(|r| r.ipv6).map(|ip| {
-                vec![ResourceRecord::AAAA {
-                    // TODO: use compression, e.g. `Name::Pointer`
-                    name: dns::Name::Labels(query.question.labels.clone()),
-                    ttl: 15,
-                    addr: ip,
-                }]
-            }),
-            dns::QueryType::SOA => {
-                if self.soa.mname == domain_name {
-                    Some(vec![ResourceRecord::SOA(self.soa.clone())])
-                } else {
-                    None
-                }
-            }
-            _ => None,
+        let Some((apex, zone)) = self.zone(&domain_name) else {
+            header.authoritative_answer = false;
+            header.response_code = response::Rcode::Refused;
+            return empty_response(header);
         };
 
-        if answer.is_none() {
+        // updates don't affect the snapshot while the query is answered
+        let storage = self.storage.load();
+
+        let query_type = query.question.query_type;
+        let (answer, last_owner) = self.resolve(&storage, domain_name, query_type);
+
+        // a name that exists but has no records of the requested type gets
+        // an empty NOERROR (NODATA) response, see:
+        // https://www.rfc-editor.org/rfc/rfc2308#section-2.2
+        // When following aliases the response code belongs to the last
+        // name in the chain, see:
+        // https://www.rfc-editor.org/rfc/rfc6604#section-2
+        if self
+            .zone(&last_owner)
+            .is_some_and(|(apex, _)| *apex != last_owner && !storage.contains(&last_owner))
+        {
             header.response_code = response::Rcode::NameError;
         }
 
-        let answer = answer.unwrap_or_default();
+        // negative responses carry the SOA of the zone of the last name in
+        // the chain, even if the answer contains the aliases leading there,
+        // see:
+        // https://www.rfc-editor.org/rfc/rfc2308#section-2.1
+        let negative_zone = self.zone(&last_owner).filter(|_| {
+            header.response_code == response::Rcode::NameError
+                || self.lookup(&storage, &last_owner, query_type).is_empty()
+        });
+
+        let (authority, additional) = if let Some((apex, zone)) = negative_zone {
+            (vec![self.negative_soa(zone, apex)], vec![])
+        } else if query_type == dns::QueryType::NS {
+            (vec![], self.additional_records(&storage, zone, &answer))
+        } else {
+            (
+                self.ns_records(zone, dns::Name::from(apex)),
+                self.additional_records(&storage, zone, &answer),
+            )
+        };
 
         response::StandardQuery {
             header,
             question: query.question,
             answer,
+            authority,
+            additional,
+            edns,
+            udp_payload_size,
+        }
+    }
+
+    /// Looks up the records of `owner` and follows CNAME records within
+    /// the zone, see
+    /// [RFC 1034](https://www.rfc-editor.org/rfc/rfc1034#section-4.3.2).
+    /// Returns the answer and the last name of the alias chain.
+    fn resolve(
+        &self,
+        storage: &Storage,
+        owner: DomainName,
+        query_type: dns::QueryType,
+    ) -> (Vec<ResourceRecord>, DomainName) {
+        let mut answer = vec![];
+        let mut owner = owner;
+        let mut visited = vec![];
+
+        loop {
+            let records = self.lookup(storage, &owner, query_type);
+            if !records.is_empty() {
+                answer.extend(records);
+                return (answer, owner);
+            }
+
+            let entry = storage.get(&owner);
+            let Some(target) = entry.and_then(|r| r.cname.clone()) else {
+                return (answer, owner);
+            };
+            let Some((_, zone)) = self.zone(&owner) else {
+                return (answer, owner);
+            };
+            answer.push(ResourceRecord::CNAME {
+                name: dns::Name::from(&owner),
+                ttl: self.ttl(zone, entry, dns::QueryType::CNAME),
+                cname: target.clone(),
+            });
+            visited.push(owner);
+
+            // targets outside of our zones have to be resolved by the client
+            if !self.is_in_zone(&target)
+                || visited.contains(&target)
+                || visited.len() >= MAX_CNAME_CHAIN_LEN
+            {
+                return (answer, target);
+            }
+
+            owner = target;
+        }
+    }
+
+    /// The records of `owner` that answer a query of the given type.
+    fn lookup(
+        &self,
+        storage: &Storage,
+        owner: &DomainName,
+        query_type: dns::QueryType,
+    ) -> Vec<ResourceRecord> {
+        match query_type {
+            dns::QueryType::ALL => self.any_records(storage, owner),
+            query_type => self.records(storage, owner, query_type),
+        }
+    }
+
+    /// All records of the given type owned by `owner`.
+    fn records(
+        &self,
+        storage: &Storage,
+        owner: &DomainName,
+        query_type: dns::QueryType,
+    ) -> Vec<ResourceRecord> {
+        let Some((apex, zone)) = self.zone(owner) else {
+            return vec![];
+        };
+        let record = storage.get(owner);
+
+        match query_type {
+            dns::QueryType::A => record
+                .and_then(|r| r.ipv4)
+                .map(|ip| ResourceRecord::A {
+                    name: dns::Name::from(owner),
+                    ttl: self.ttl(zone, record, dns::QueryType::A),
+                    addr: ip,
+                })
+                .into_iter()
+                .collect(),
+            dns::QueryType::AAAA => record
+                .and_then(|r| r.ipv6)
+                .map(|ip| ResourceRecord::AAAA {
+                    name: dns::Name::from(owner),
+                    ttl: self.ttl(zone, record, dns::QueryType::AAAA),
+                    addr: ip,
+                })
+                .into_iter()
+                .collect(),
+            dns::QueryType::CNAME => record
+                .and_then(|r| r.cname.clone())
+                .map(|cname| ResourceRecord::CNAME {
+                    name: dns::Name::from(owner),
+                    ttl: self.ttl(zone, record, dns::QueryType::CNAME),
+                    cname,
+                })
+                .into_iter()
+                .collect(),
+            dns::QueryType::TXT => record
+                .map(|r| r.txt.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .map(|text| ResourceRecord::TXT {
+                    name: dns::Name::from(owner),
+                    ttl: self.ttl(zone, record, dns::QueryType::TXT),
+                    text: text.clone(),
+                })
+                .collect(),
+            dns::QueryType::MX => record
+                .map(|r| r.mx.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .map(|mx| ResourceRecord::MX {
+                    name: dns::Name::from(owner),
+                    ttl: self.ttl(zone, record, dns::QueryType::MX),
+                    preference: mx.preference,
+                    exchange: mx.exchange.clone(),
+                })
+                .collect(),
+            dns::QueryType::SRV => record
+                .map(|r| r.srv.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .map(|srv| ResourceRecord::SRV {
+                    name: dns::Name::from(owner),
+                    ttl: self.ttl(zone, record, dns::QueryType::SRV),
+                    priority: srv.priority,
+                    weight: srv.weight,
+                    port: srv.port,
+                    target: srv.target.clone(),
+                })
+                .collect(),
+            dns::QueryType::CAA => {
+                let caa = if apex == owner {
+                    zone.caa.as_slice()
+                } else {
+                    record.map(|r| r.caa.as_slice()).unwrap_or_default()
+                };
+
+                caa.iter()
+                    .map(|caa| ResourceRecord::CAA {
+                        name: dns::Name::from(owner),
+                        ttl: self.ttl(zone, record, dns::QueryType::CAA),
+                        flags: caa.flags,
+                        tag: caa.tag.clone(),
+                        value: caa.value.clone(),
+                    })
+                    .collect()
+            }
+            dns::QueryType::PTR => storage
+                .pointers(owner)
+                .iter()
+                .map(|hostname| ResourceRecord::PTR {
+                    name: dns::Name::from(owner),
+                    ttl: self.ttl(zone, None, dns::QueryType::PTR),
+                    ptrdname: hostname.clone(),
+                })
+                .collect(),
+            // the owner name keeps the case used in the question
+            dns::QueryType::SOA if apex == owner => vec![self.soa_record(zone, owner)],
+            dns::QueryType::NS if apex == owner => self.ns_records(zone, dns::Name::from(owner)),
+            _ => vec![],
         }
     }
+
+    /// Answers ANY queries as configured by [`AnyQuery`]. We never return
+    /// all records of a name, see
+    /// [RFC 8482](https://www.rfc-editor.org/rfc/rfc8482#section-4.2).
+    fn any_records(&self, storage: &Storage, owner: &DomainName) -> Vec<ResourceRecord> {
+        use dns::QueryType::*;
+
+        if self.any_query == AnyQuery::Addresses {
+            let addresses: Vec<_> = [A, AAAA]
+                .into_iter()
+                .flat_map(|query_type| self.records(storage, owner, query_type))
+                .collect();
+
+            if !addresses.is_empty() {
+                return addresses;
+            }
+        }
+
+        [CNAME, A, AAAA, MX, SRV, TXT, SOA, NS, CAA]
+            .into_iter()
+            .map(|query_type| self.records(storage, owner, query_type))
+            .find(|records| !records.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// The zone the name belongs to together with its apex. Zones may be
+    /// nested, so the zone with the longest matching apex is chosen.
+    fn zone(&self, name: &DomainName) -> Option<(&DomainName, &Zone)> {
+        self.zones
+            .iter()
+            .filter(|(apex, _)| name.is_subdomain_of(apex))
+            .max_by_key(|(apex, _)| apex.labels().count())
+    }
+
+    fn is_in_zone(&self, name: &DomainName) -> bool {
+        self.zone(name).is_some()
+    }
+
+    /// The SOA record of the zone, owned by `apex`. Unless configured
+    /// otherwise, its TTL is the MINIMUM field of the SOA.
+    fn soa_record(&self, zone: &Zone, apex: &DomainName) -> ResourceRecord {
+        let ttl = zone
+            .ttl
+            .get(dns::QueryType::SOA)
+            .unwrap_or(zone.soa.minimum);
+
+        soa_record(zone, apex, ttl)
+    }
+
+    /// NS records of the zone, owned by `apex`.
+    fn ns_records(&self, zone: &Zone, apex: dns::Name) -> Vec<ResourceRecord> {
+        zone.name_servers
+            .iter()
+            .map(|name_server| ResourceRecord::NS {
+                name: apex.clone(),
+                ttl: self.ttl(zone, None, dns::QueryType::NS),
+                nsdname: name_server.clone(),
+            })
+            .collect()
+    }
+
+    /// Address records of the names the answer refers to, e.g. mail
+    /// exchanges or service targets, and of all name servers, so that
+    /// resolvers don't need to look them up separately, see
+    /// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-3.3.9).
+    /// Only names that are part of one of our zones are considered.
+    fn additional_records(
+        &self,
+        storage: &Storage,
+        zone: &Zone,
+        answer: &[ResourceRecord],
+    ) -> Vec<ResourceRecord> {
+        let mut names: Vec<&DomainName> = vec![];
+        for name in answer
+            .iter()
+            .filter_map(additional_name)
+            .chain(&zone.name_servers)
+        {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        names
+            .into_iter()
+            .filter_map(|name| Some((self.zone(name)?.1, name, storage.get(name)?)))
+            .flat_map(|(zone, name, entry)| self.address_records(zone, name, entry))
+            .collect()
+    }
+
+    fn address_records(
+        &self,
+        zone: &Zone,
+        name: &DomainName,
+        entry: &SubDomainEntry,
+    ) -> Vec<ResourceRecord> {
+        let ipv4 = entry.ipv4.map(|addr| ResourceRecord::A {
+            name: dns::Name::from(name),
+            ttl: self.ttl(zone, Some(entry), dns::QueryType::A),
+            addr,
+        });
+        let ipv6 = entry.ipv6.map(|addr| ResourceRecord::AAAA {
+            name: dns::Name::from(name),
+            ttl: self.ttl(zone, Some(entry), dns::QueryType::AAAA),
+            addr,
+        });
+
+        ipv4.into_iter().chain(ipv6).collect()
+    }
+
+    /// The TTL of a record of `entry`. The TTLs of the entry take
+    /// precedence over the ones of the zone.
+    fn ttl(&self, zone: &Zone, entry: Option<&SubDomainEntry>, record_type: dns::QueryType) -> u32 {
+        entry
+            .and_then(|entry| entry.ttl.get(record_type))
+            .or_else(|| zone.ttl.get(record_type))
+            .unwrap_or(DEFAULT_TTL)
+    }
+
+    /// The SOA record that is added to the authority section of negative
+    /// responses. Resolvers cache those responses for the TTL of this
+    /// record, which must not exceed the MINIMUM field, see
+    /// [RFC 2308](https://www.rfc-editor.org/rfc/rfc2308#section-5).
+    fn negative_soa(&self, zone: &Zone, apex: &DomainName) -> ResourceRecord {
+        let ttl = zone
+            .ttl
+            .get(dns::QueryType::SOA)
+            .map_or(zone.soa.minimum, |ttl| ttl.min(zone.soa.minimum));
+
+        soa_record(zone, apex, ttl)
+    }
+}
+
+/// The SOA record of the zone, owned by `apex`, which keeps the case of the
+/// question.
+fn soa_record(zone: &Zone, apex: &DomainName, ttl: u32) -> ResourceRecord {
+    ResourceRecord::SOA {
+        ttl,
+        soa: dns::StartOfAuthority {
+            mname: apex.clone(),
+            ..zone.soa.clone()
+        },
+    }
+}
+
+/// The name whose addresses belong to the additional section of a response
+/// containing `rr`.
+fn additional_name(rr: &ResourceRecord) -> Option<&DomainName> {
+    match rr {
+        ResourceRecord::MX { exchange, .. } => Some(exchange),
+        ResourceRecord::SRV { target, .. } => Some(target),
+        _ => None,
+    }
 }
 
 fn unsupported_response(header: dns::RawHeader) -> dns::Response {
+    // only the header is sent back, so the question is omitted as well
     let header = dns::RawHeader {
+        qr: dns::Qr::Response,
         authoritative_answer: true,
         truncated: false,
         recursion_available: false,
+        qd_count: 0,
         an_count: 0,
+        ns_count: 0,
+        ar_count: 0,
         response_code: response::Rcode::NotImplemented,
         ..header
     };
@@ -145,22 +692,165 @@ fn unsupported_response(header: dns::RawHeader) -> dns::Response {
     dns::Response::NotImplemented(response::NotImplemented { header })
 }
 
+fn format_error_response(header: dns::RawHeader) -> dns::Response {
+    // the question couldn't be parsed, so it is omitted
+    let header = dns::RawHeader {
+        qr: dns::Qr::Response,
+        authoritative_answer: false,
+        truncated: false,
+        recursion_available: false,
+        qd_count: 0,
+        an_count: 0,
+        ns_count: 0,
+        ar_count: 0,
+        response_code: response::Rcode::FormatError,
+        ..header
+    };
+
+    dns::Response::FormatError(response::FormatError { header })
+}
+
 #[cfg(test)]
 mod test {
-    use std::{
-        collections::HashMap,
-        sync::{Arc, Mutex},
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpSocket, TcpStream},
+        sync::Semaphore,
     };
 
+    use super::Zone;
     use crate::{
-        Storage,
+        SharedStorage, Storage,
         dns::{self, DomainName},
+        storage::{CaaProperty, MailExchange, Service, SubDomainEntry, Ttls},
     };
 
-    #[test]
-    fn it_handles_soa_request() {
+    fn server() -> dns::Server {
         let mname = DomainName::from("dyn.example.com");
-        let sub_domains = HashMap::new();
+        let sub_domains = HashMap::from([
+            (
+                DomainName::from("test"),
+                SubDomainEntry {
+                    ipv4: Some(std::net::Ipv4Addr::new(1, 2, 3, 4)),
+                    token: "super_secure".to_string(),
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("ns"),
+                SubDomainEntry {
+                    ipv4: Some(std::net::Ipv4Addr::new(10, 0, 0, 1)),
+                    ipv6: Some(std::net::Ipv6Addr::LOCALHOST),
+                    token: "super_secure".to_string(),
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("alias"),
+                SubDomainEntry {
+                    cname: Some(DomainName::from("test.dyn.example.com")),
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("alias-of-alias"),
+                SubDomainEntry {
+                    cname: Some(DomainName::from("alias.dyn.example.com")),
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("external"),
+                SubDomainEntry {
+                    cname: Some(DomainName::from("www.example.org")),
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("dangling"),
+                SubDomainEntry {
+                    cname: Some(DomainName::from("unknown.dyn.example.com")),
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("_acme-challenge.test"),
+                SubDomainEntry {
+                    txt: vec!["first".to_string(), "second".to_string()],
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("mail"),
+                SubDomainEntry {
+                    ipv4: Some(std::net::Ipv4Addr::new(10, 0, 0, 2)),
+                    token: "super_secure".to_string(),
+                    mx: vec![
+                        MailExchange {
+                            preference: 10,
+                            exchange: DomainName::from("mail.dyn.example.com"),
+                        },
+                        MailExchange {
+                            preference: 20,
+                            exchange: DomainName::from("mx.example.org"),
+                        },
+                    ],
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("_sip._udp.test"),
+                SubDomainEntry {
+                    srv: vec![Service {
+                        priority: 10,
+                        weight: 5,
+                        port: 5060,
+                        target: DomainName::from("test.dyn.example.com"),
+                    }],
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("shop"),
+                SubDomainEntry {
+                    ipv4: Some(std::net::Ipv4Addr::new(10, 0, 0, 3)),
+                    token: "super_secure".to_string(),
+                    ttl: Ttls {
+                        a: Some(5),
+                        ..Default::default()
+                    },
+                    caa: vec![CaaProperty {
+                        flags: 128,
+                        tag: "issue".to_string(),
+                        value: "ca.example.net".to_string(),
+                    }],
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("*.test"),
+                SubDomainEntry {
+                    cname: Some(DomainName::from("test.dyn.example.com")),
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("lab-alias"),
+                SubDomainEntry {
+                    cname: Some(DomainName::from("unknown.lab.dyn.example.com")),
+                    ..Default::default()
+                },
+            ),
+            (
+                DomainName::from("loop"),
+                SubDomainEntry {
+                    cname: Some(DomainName::from("loop.dyn.example.com")),
+                    ..Default::default()
+                },
+            ),
+        ]);
 
         let soa = dns::StartOfAuthority {
             mname: mname.clone(),
@@ -172,14 +862,96 @@ mod test {
             minimum: 400,
         };
 
-        let storage = Arc::new(Mutex::new(Storage::new(mname, sub_domains)));
-        let server = dns::Server {
+        let mut storage = Storage::new(HashMap::from([
+            (mname.clone(), sub_domains),
+            (
+                DomainName::from("example.net"),
+                HashMap::from([(
+                    DomainName::from("www"),
+                    SubDomainEntry {
+                        ipv4: Some(std::net::Ipv4Addr::new(192, 0, 2, 10)),
+                        ..Default::default()
+                    },
+                )]),
+            ),
+            (
+                DomainName::from("lab.dyn.example.com"),
+                HashMap::from([(
+                    DomainName::from("host"),
+                    SubDomainEntry {
+                        ipv4: Some(std::net::Ipv4Addr::new(10, 0, 1, 1)),
+                        ..Default::default()
+                    },
+                )]),
+            ),
+        ]));
+        storage.insert_pointer(
+            std::net::IpAddr::from([192, 0, 2, 1]),
+            DomainName::from("test.dyn.example.com"),
+        );
+        let storage = SharedStorage::new(storage);
+        let zone = Zone {
             soa,
-            storage,
-            listen_addr: std::net::IpAddr::from([10, 0, 0, 1]),
-            listen_port: 54,
+            name_servers: vec![
+                DomainName::from("ns.dyn.example.com"),
+                DomainName::from("ns.example.org"),
+            ],
+            caa: vec![CaaProperty {
+                flags: 0,
+                tag: "issue".to_string(),
+                value: "letsencrypt.org".to_string(),
+            }],
+            ttl: Ttls {
+                default: Some(60),
+                ns: Some(3600),
+                ..Default::default()
+            },
         };
-        let query = dns::request::StandardQuery {
+        let reverse_apex = DomainName::from("2.0.192.in-addr.arpa");
+        let reverse_zone = Zone {
+            soa: dns::StartOfAuthority {
+                mname: reverse_apex.clone(),
+                ..zone.soa.clone()
+            },
+            caa: vec![],
+            ..zone.clone()
+        };
+        let other_zone = Zone {
+            soa: dns::StartOfAuthority {
+                mname: DomainName::from("example.net"),
+                rname: DomainName::from("hostmaster.example.net"),
+                serial: 7,
+                ..zone.soa.clone()
+            },
+            name_servers: vec![DomainName::from("ns.example.org")],
+            caa: vec![],
+            ttl: Ttls::default(),
+        };
+        let nested_zone = Zone {
+            soa: dns::StartOfAuthority {
+                mname: DomainName::from("lab.dyn.example.com"),
+                serial: 42,
+                ..zone.soa.clone()
+            },
+            name_servers: vec![DomainName::from("ns.dyn.example.com")],
+            caa: vec![],
+            ttl: Ttls::default(),
+        };
+
+        dns::Server {
+            zones: HashMap::from([
+                (mname, zone),
+                (reverse_apex, reverse_zone),
+                (DomainName::from("example.net"), other_zone),
+                (DomainName::from("lab.dyn.example.com"), nested_zone),
+            ]),
+            any_query: super::AnyQuery::Minimal,
+            storage,
+        }
+    }
+
+    fn query(name: &str, query_type: dns::QueryType) -> dns::request::StandardQuery {
+        dns::request::StandardQuery {
             header: dns::request::Header {
                 id: 1234,
                 truncated: false,
@@ -188,19 +960,999 @@ mod test {
             },
 
             question: dns::Question {
-                labels: ["dyn", "example", "com"]
-                    .iter()
-                    .map(|s| s.to_string())
-                    .collect(),
-                name: "dyn.example.com".to_string(),
-                query_type: dns::QueryType::SOA,
+                labels: name.split('.').map(|s| s.to_string()).collect(),
+                name: name.to_string(),
+                query_type,
                 query_class: dns::QueryClass::IN,
             },
-        };
+            edns: None,
+        }
+    }
+
+    fn edns(version: u8) -> dns::Edns {
+        dns::Edns {
+            udp_payload_size: 4096,
+            extended_rcode: 0,
+            version,
+            dnssec_ok: true,
+            options: vec![dns::EdnsOption {
+                code: 10,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            }],
+        }
+    }
+
+    #[test]
+    fn it_handles_soa_request() {
+        let server = server();
+        let query = query("dyn.example.com", dns::QueryType::SOA);
 
         let msg = server.standard_query(query);
 
         assert!(msg.header.authoritative_answer);
         assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
     }
+
+    #[test]
+    fn it_answers_apex_queries_of_fully_qualified_zone() {
+        let mut server = server();
+        let mut zone = server
+            .zones
+            .remove(&DomainName::from("dyn.example.com"))
+            .unwrap();
+        zone.soa.mname = DomainName::from("dyn.example.com.");
+        server.zones.insert(zone.soa.mname.clone(), zone);
+
+        let msg = server.standard_query(query("dyn.example.com", dns::QueryType::SOA));
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert!(matches!(
+            msg.answer.as_slice(),
+            [dns::ResourceRecord::SOA { .. }]
+        ));
+
+        let msg = server.standard_query(query("dyn.example.com", dns::QueryType::NS));
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert_eq!(msg.answer.len(), 2);
+    }
+
+    #[test]
+    fn it_echoes_edns() {
+        let server = server();
+        let query = dns::request::StandardQuery {
+            edns: Some(edns(0)),
+            ..query("dyn.example.com", dns::QueryType::SOA)
+        };
+
+        let msg = server.standard_query(query);
+
+        let edns = msg.edns.unwrap();
+        assert_eq!(edns.udp_payload_size, super::EDNS_UDP_PAYLOAD_SIZE);
+        assert_eq!(edns.version, 0);
+        assert!(edns.dnssec_ok);
+        assert!(edns.options.is_empty());
+        assert_eq!(msg.answer.len(), 1);
+        assert_eq!(msg.udp_payload_size, super::EDNS_UDP_PAYLOAD_SIZE);
+    }
+
+    #[test]
+    fn it_limits_udp_payload_size_to_512_bytes_without_edns() {
+        let server = server();
+        let query = query("dyn.example.com", dns::QueryType::SOA);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.udp_payload_size, dns::response::MAX_UDP_SIZE);
+    }
+
+    #[test]
+    fn it_limits_udp_payload_size_to_at_least_512_bytes() {
+        let server = server();
+        let query = dns::request::StandardQuery {
+            edns: Some(dns::Edns {
+                udp_payload_size: 256,
+                ..edns(0)
+            }),
+            ..query("dyn.example.com", dns::QueryType::SOA)
+        };
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.udp_payload_size, dns::response::MAX_UDP_SIZE);
+    }
+
+    #[test]
+    fn it_omits_edns_if_not_requested() {
+        let server = server();
+        let query = query("dyn.example.com", dns::QueryType::SOA);
+
+        let msg = server.standard_query(query);
+
+        assert!(msg.edns.is_none());
+    }
+
+    #[test]
+    fn it_rejects_unsupported_edns_version() {
+        let server = server();
+        let query = dns::request::StandardQuery {
+            edns: Some(edns(1)),
+            ..query("dyn.example.com", dns::QueryType::SOA)
+        };
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::BadVersion);
+        assert!(msg.answer.is_empty());
+
+        let raw = dns::Response::StandardQuery(msg).as_u8();
+        // header RCODE holds the lower four bits of BADVERS (16)
+        assert_eq!(raw[3] & 0b1111, 0);
+        // the OPT record is the last record, its TTL starts with the
+        // upper eight bits of the extended RCODE
+        let opt = &raw[raw.len() - 11..];
+        assert_eq!(&opt[0..3], &[0, 0, 41]);
+        assert_eq!(opt[5], 1);
+    }
+
+    #[test]
+    fn it_matches_names_case_insensitively() {
+        let server = server();
+        let query = query("TeSt.DYN.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        let [dns::ResourceRecord::A { name, addr, .. }] = msg.answer.as_slice() else {
+            panic!("expected a single A record");
+        };
+        assert_eq!(*addr, std::net::Ipv4Addr::new(1, 2, 3, 4));
+        let dns::Name::Labels(labels) = name else {
+            panic!("expected labels");
+        };
+        assert_eq!(labels, &msg.question.labels);
+        assert_eq!(msg.question.name, "TeSt.DYN.example.com");
+    }
+
+    #[test]
+    fn it_preserves_case_of_soa_owner() {
+        let server = server();
+        let query = query("DYN.Example.com", dns::QueryType::SOA);
+
+        let msg = server.standard_query(query);
+
+        let [dns::ResourceRecord::SOA { soa, .. }] = msg.answer.as_slice() else {
+            panic!("expected a single SOA record");
+        };
+        assert_eq!(soa.mname.to_string(), "DYN.Example.com");
+    }
+
+    #[test]
+    fn it_adds_soa_to_nxdomain_response() {
+        let server = server();
+        let query = query("unknown.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NameError);
+        assert!(msg.answer.is_empty());
+        let [dns::ResourceRecord::SOA { soa, .. }] = msg.authority.as_slice() else {
+            panic!("expected a single SOA record");
+        };
+        assert_eq!(soa.mname, DomainName::from("dyn.example.com"));
+        assert_eq!(soa.minimum, 400);
+    }
+
+    #[test]
+    fn it_adds_soa_to_nodata_response() {
+        let server = server();
+        let query = query("test.dyn.example.com", dns::QueryType::MX);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert!(msg.answer.is_empty());
+        assert!(matches!(
+            msg.authority.as_slice(),
+            [dns::ResourceRecord::SOA { .. }]
+        ));
+    }
+
+    #[test]
+    fn it_returns_nodata_for_missing_address_type() {
+        let server = server();
+        let query = query("test.dyn.example.com", dns::QueryType::AAAA);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert!(msg.answer.is_empty());
+    }
+
+    #[test]
+    fn it_returns_nodata_for_apex() {
+        let server = server();
+        let query = query("dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert!(msg.answer.is_empty());
+    }
+
+    #[test]
+    fn it_returns_nxdomain_for_unsupported_type_of_unknown_name() {
+        let server = server();
+        let query = query("unknown.dyn.example.com", dns::QueryType::MX);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NameError);
+    }
+
+    #[test]
+    fn it_omits_soa_from_positive_response() {
+        let server = server();
+        let query = query("test.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert!(
+            !msg.authority
+                .iter()
+                .any(|rr| matches!(rr, dns::ResourceRecord::SOA { .. }))
+        );
+    }
+
+    #[test]
+    fn it_answers_ns_query_with_glue() {
+        let server = server();
+        let query = query("dyn.example.com", dns::QueryType::NS);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        let nsdnames: Vec<_> = msg
+            .answer
+            .iter()
+            .map(|rr| match rr {
+                dns::ResourceRecord::NS { nsdname, .. } => nsdname.to_string(),
+                rr => panic!("unexpected record: {rr:?}"),
+            })
+            .collect();
+        assert_eq!(nsdnames, vec!["ns.dyn.example.com", "ns.example.org"]);
+        assert!(msg.authority.is_empty());
+        assert!(matches!(
+            msg.additional.as_slice(),
+            [
+                dns::ResourceRecord::A { .. },
+                dns::ResourceRecord::AAAA { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn it_adds_ns_to_authority_of_positive_response() {
+        let server = server();
+        let query = query("test.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.authority.len(), 2);
+        assert!(
+            msg.authority
+                .iter()
+                .all(|rr| matches!(rr, dns::ResourceRecord::NS { .. }))
+        );
+        assert_eq!(msg.additional.len(), 2);
+    }
+
+    #[test]
+    fn it_returns_nodata_for_ns_query_below_apex() {
+        let server = server();
+        let query = query("test.dyn.example.com", dns::QueryType::NS);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert!(msg.answer.is_empty());
+    }
+
+    #[test]
+    fn it_answers_any_query_with_single_rrset() {
+        let server = server();
+        let query = query("ns.dyn.example.com", dns::QueryType::ALL);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert!(matches!(
+            msg.answer.as_slice(),
+            [dns::ResourceRecord::A { .. }]
+        ));
+    }
+
+    #[test]
+    fn it_answers_any_query_with_all_addresses() {
+        let server = dns::Server {
+            any_query: super::AnyQuery::Addresses,
+            ..server()
+        };
+        let query = query("ns.dyn.example.com", dns::QueryType::ALL);
+
+        let msg = server.standard_query(query);
+
+        assert!(matches!(
+            msg.answer.as_slice(),
+            [
+                dns::ResourceRecord::A { .. },
+                dns::ResourceRecord::AAAA { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn it_answers_any_query_for_apex_with_soa() {
+        let server = dns::Server {
+            any_query: super::AnyQuery::Addresses,
+            ..server()
+        };
+        let query = query("dyn.example.com", dns::QueryType::ALL);
+
+        let msg = server.standard_query(query);
+
+        assert!(matches!(
+            msg.answer.as_slice(),
+            [dns::ResourceRecord::SOA { .. }]
+        ));
+    }
+
+    #[test]
+    fn it_answers_any_query_for_unknown_name_with_nxdomain() {
+        let server = server();
+        let query = query("unknown.dyn.example.com", dns::QueryType::ALL);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NameError);
+    }
+
+    fn cname_targets(answer: &[dns::ResourceRecord]) -> Vec<String> {
+        answer
+            .iter()
+            .filter_map(|rr| match rr {
+                dns::ResourceRecord::CNAME { cname, .. } => Some(cname.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_follows_cname_chain() {
+        let server = server();
+        let query = query("alias-of-alias.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert_eq!(
+            cname_targets(&msg.answer),
+            vec!["alias.dyn.example.com", "test.dyn.example.com"]
+        );
+        let Some(dns::ResourceRecord::A { name, addr, .. }) = msg.answer.last() else {
+            panic!("expected an A record");
+        };
+        assert_eq!(*addr, std::net::Ipv4Addr::new(1, 2, 3, 4));
+        let dns::Name::Labels(labels) = name else {
+            panic!("expected labels");
+        };
+        assert_eq!(labels, &["test", "dyn", "example", "com"]);
+    }
+
+    #[test]
+    fn it_answers_cname_query_without_following() {
+        let server = server();
+        let query = query("alias-of-alias.dyn.example.com", dns::QueryType::CNAME);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(cname_targets(&msg.answer), vec!["alias.dyn.example.com"]);
+        assert_eq!(msg.answer.len(), 1);
+    }
+
+    #[test]
+    fn it_returns_nodata_for_cname_to_host_without_address() {
+        let server = server();
+        let query = query("alias.dyn.example.com", dns::QueryType::AAAA);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert_eq!(cname_targets(&msg.answer), vec!["test.dyn.example.com"]);
+        assert_eq!(soa_serial(&msg.authority), Some(123));
+    }
+
+    #[test]
+    fn it_does_not_follow_cname_out_of_zone() {
+        let server = server();
+        let query = query("external.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert_eq!(cname_targets(&msg.answer), vec!["www.example.org"]);
+    }
+
+    #[test]
+    fn it_returns_nxdomain_for_dangling_cname() {
+        let server = server();
+        let query = query("dangling.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NameError);
+        assert_eq!(cname_targets(&msg.answer), vec!["unknown.dyn.example.com"]);
+        assert_eq!(soa_serial(&msg.authority), Some(123));
+    }
+
+    #[test]
+    fn it_adds_soa_of_zone_of_cname_target_to_negative_response() {
+        let server = server();
+        let query = query("lab-alias.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NameError);
+        assert_eq!(
+            cname_targets(&msg.answer),
+            vec!["unknown.lab.dyn.example.com"]
+        );
+        assert_eq!(soa_serial(&msg.authority), Some(42));
+    }
+
+    #[test]
+    fn it_stops_at_cname_loops() {
+        let server = server();
+        let query = query("loop.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(cname_targets(&msg.answer), vec!["loop.dyn.example.com"]);
+    }
+
+    #[test]
+    fn it_answers_txt_query_with_all_values() {
+        let server = server();
+        let query = query("_acme-challenge.test.dyn.example.com", dns::QueryType::TXT);
+
+        let msg = server.standard_query(query);
+
+        let texts: Vec<_> = msg
+            .answer
+            .iter()
+            .map(|rr| match rr {
+                dns::ResourceRecord::TXT { text, .. } => text.as_str(),
+                _ => panic!("unexpected record: {rr:?}"),
+            })
+            .collect();
+        assert_eq!(texts, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn it_answers_mx_query_with_exchange_addresses() {
+        let server = server();
+        let query = query("mail.dyn.example.com", dns::QueryType::MX);
+
+        let msg = server.standard_query(query);
+
+        let exchanges: Vec<_> = msg
+            .answer
+            .iter()
+            .map(|rr| match rr {
+                dns::ResourceRecord::MX {
+                    preference,
+                    exchange,
+                    ..
+                } => (*preference, exchange.to_string()),
+                _ => panic!("unexpected record: {rr:?}"),
+            })
+            .collect();
+        assert_eq!(
+            exchanges,
+            vec![
+                (10, "mail.dyn.example.com".to_string()),
+                (20, "mx.example.org".to_string())
+            ]
+        );
+        // the in-zone exchange comes before the glue of the name server
+        assert!(matches!(
+            msg.additional.as_slice(),
+            [
+                dns::ResourceRecord::A { addr, .. },
+                dns::ResourceRecord::A { .. },
+                dns::ResourceRecord::AAAA { .. },
+            ] if *addr == std::net::Ipv4Addr::new(10, 0, 0, 2)
+        ));
+    }
+
+    #[test]
+    fn it_answers_srv_query_with_target_addresses() {
+        let server = server();
+        let query = query("_sip._udp.test.dyn.example.com", dns::QueryType::SRV);
+
+        let msg = server.standard_query(query);
+
+        assert!(matches!(
+            msg.answer.as_slice(),
+            [dns::ResourceRecord::SRV { priority: 10, weight: 5, port: 5060, target, .. }]
+                if target.to_string() == "test.dyn.example.com"
+        ));
+        assert!(matches!(
+            msg.additional.first(),
+            Some(dns::ResourceRecord::A { addr, .. })
+                if *addr == std::net::Ipv4Addr::new(1, 2, 3, 4)
+        ));
+    }
+
+    fn caa_values(answer: &[dns::ResourceRecord]) -> Vec<&str> {
+        answer
+            .iter()
+            .map(|rr| match rr {
+                dns::ResourceRecord::CAA { value, .. } => value.as_str(),
+                _ => panic!("unexpected record: {rr:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_answers_caa_query_for_apex() {
+        let server = server();
+        let query = query("dyn.example.com", dns::QueryType::CAA);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(caa_values(&msg.answer), vec!["letsencrypt.org"]);
+    }
+
+    #[test]
+    fn it_answers_caa_query_for_host() {
+        let server = server();
+        let query = query("shop.dyn.example.com", dns::QueryType::CAA);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(caa_values(&msg.answer), vec!["ca.example.net"]);
+    }
+
+    #[test]
+    fn it_returns_nodata_for_host_without_caa() {
+        let server = server();
+        let query = query("test.dyn.example.com", dns::QueryType::CAA);
+
+        let msg = server.standard_query(query);
+
+        assert!(msg.answer.is_empty());
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+    }
+
+    #[test]
+    fn it_answers_ptr_query() {
+        let server = server();
+        let query = query("1.2.0.192.in-addr.arpa", dns::QueryType::PTR);
+
+        let msg = server.standard_query(query);
+
+        assert!(matches!(
+            msg.answer.as_slice(),
+            [dns::ResourceRecord::PTR { ptrdname, .. }]
+                if ptrdname.to_string() == "test.dyn.example.com"
+        ));
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+    }
+
+    #[test]
+    fn it_returns_nxdomain_with_reverse_zone_soa_for_unknown_address() {
+        let server = server();
+        let query = query("2.2.0.192.in-addr.arpa", dns::QueryType::PTR);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NameError);
+        assert!(matches!(
+            msg.authority.as_slice(),
+            [dns::ResourceRecord::SOA { soa, .. }] if soa.mname.to_string() == "2.0.192.in-addr.arpa"
+        ));
+    }
+
+    #[test]
+    fn it_answers_soa_query_for_reverse_zone() {
+        let server = server();
+        let query = query("2.0.192.in-addr.arpa", dns::QueryType::SOA);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.answer.len(), 1);
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+    }
+
+    #[test]
+    fn it_ignores_pointers_outside_of_reverse_zones() {
+        let server = server();
+        let result = server.storage.update(|storage| {
+            storage.insert_pointer(
+                std::net::IpAddr::from([198, 51, 100, 1]),
+                DomainName::from("test.dyn.example.com"),
+            );
+            Ok::<_, ()>(())
+        });
+        assert!(result.is_ok());
+        let query = query("1.100.51.198.in-addr.arpa", dns::QueryType::PTR);
+
+        let msg = server.standard_query(query);
+
+        assert!(msg.answer.is_empty());
+    }
+
+    #[test]
+    fn it_synthesizes_answer_from_wildcard() {
+        let server = server();
+        let query = query("www.test.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert!(matches!(
+            msg.answer.as_slice(),
+            [
+                dns::ResourceRecord::CNAME { name: dns::Name::Labels(labels), .. },
+                dns::ResourceRecord::A { .. },
+            ] if labels.join(".") == "www.test.dyn.example.com"
+        ));
+    }
+
+    #[test]
+    fn it_prefers_explicit_entry_over_wildcard() {
+        let server = server();
+        let query = query("_acme-challenge.test.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert!(msg.answer.is_empty());
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+    }
+
+    #[test]
+    fn it_answers_malformed_request_with_formerr() {
+        let server = server();
+        let header = dns::RawHeader {
+            id: 1234,
+            qr: dns::Qr::Query,
+            opcode: dns::OpCode::StandardQuery,
+            truncated: false,
+            authoritative_answer: false,
+            recursion_desired: true,
+            recursion_available: false,
+            response_code: dns::response::Rcode::NoError,
+            qd_count: 2,
+            an_count: 0,
+            ns_count: 0,
+            ar_count: 1,
+        };
+
+        let response = server.creat_response(dns::Request::Malformed(header));
+
+        let dns::Response::FormatError(response) = response else {
+            panic!("expected a FORMERR response");
+        };
+        assert_eq!(response.header.id, 1234);
+        assert!(response.header.recursion_desired);
+        assert_eq!(
+            response.header.response_code,
+            dns::response::Rcode::FormatError
+        );
+        assert_eq!(response.header.qd_count, 0);
+        assert_eq!(response.header.ar_count, 0);
+    }
+
+    #[test]
+    fn it_answers_unsupported_request_without_question() {
+        let server = server();
+        let header = dns::RawHeader {
+            id: 1234,
+            qr: dns::Qr::Query,
+            opcode: dns::OpCode::Reserved(5),
+            truncated: false,
+            authoritative_answer: false,
+            recursion_desired: false,
+            recursion_available: false,
+            response_code: dns::response::Rcode::NoError,
+            qd_count: 1,
+            an_count: 0,
+            ns_count: 1,
+            ar_count: 0,
+        };
+
+        let response = server.creat_response(dns::Request::Unsupported(header));
+
+        assert_eq!(
+            response.as_u8(),
+            vec![0x04, 0xd2, 0xac, 0x04, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn it_refuses_queries_of_other_classes() {
+        let server = server();
+        let mut query = query("test.dyn.example.com", dns::QueryType::A);
+        query.question.query_class = dns::QueryClass::CH;
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::Refused);
+        assert!(msg.answer.is_empty());
+    }
+
+    #[test]
+    fn it_answers_queries_of_class_any() {
+        let server = server();
+        let mut query = query("test.dyn.example.com", dns::QueryType::A);
+        query.question.query_class = dns::QueryClass::ANY;
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert_eq!(msg.answer.len(), 1);
+    }
+
+    #[test]
+    fn it_refuses_names_outside_of_zone() {
+        let server = server();
+        let query = query("google.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::Refused);
+        assert!(!msg.header.authoritative_answer);
+        assert!(msg.authority.is_empty());
+    }
+
+    fn soa_serial(records: &[dns::ResourceRecord]) -> Option<u32> {
+        match records {
+            [dns::ResourceRecord::SOA { soa, .. }] => Some(soa.serial),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn it_answers_queries_of_other_zone() {
+        let server = server();
+
+        let msg = server.standard_query(query("www.example.net", dns::QueryType::A));
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert!(msg.header.authoritative_answer);
+        assert!(matches!(
+            msg.answer.as_slice(),
+            [dns::ResourceRecord::A { addr, .. }] if *addr == std::net::Ipv4Addr::new(192, 0, 2, 10)
+        ));
+        let nsdnames: Vec<_> = msg
+            .authority
+            .iter()
+            .map(|rr| match rr {
+                dns::ResourceRecord::NS { nsdname, .. } => nsdname.to_string(),
+                rr => panic!("unexpected record: {rr:?}"),
+            })
+            .collect();
+        assert_eq!(nsdnames, vec!["ns.example.org"]);
+    }
+
+    #[test]
+    fn it_returns_nxdomain_with_soa_of_other_zone() {
+        let server = server();
+
+        let msg = server.standard_query(query("unknown.example.net", dns::QueryType::A));
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NameError);
+        assert_eq!(soa_serial(&msg.authority), Some(7));
+    }
+
+    #[test]
+    fn it_routes_names_to_nested_zone() {
+        let server = server();
+
+        let msg = server.standard_query(query("host.lab.dyn.example.com", dns::QueryType::A));
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NoError);
+        assert_eq!(msg.answer.len(), 1);
+
+        let msg = server.standard_query(query("lab.dyn.example.com", dns::QueryType::SOA));
+        assert_eq!(soa_serial(&msg.answer), Some(42));
+
+        let msg = server.standard_query(query("unknown.lab.dyn.example.com", dns::QueryType::A));
+        assert_eq!(msg.header.response_code, dns::response::Rcode::NameError);
+        assert_eq!(soa_serial(&msg.authority), Some(42));
+    }
+
+    #[test]
+    fn it_refuses_names_ending_with_zone_label() {
+        let server = server();
+        let query = query("test.notdyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::Refused);
+    }
+
+    #[test]
+    fn it_refuses_names_with_escaped_dot_in_zone_label() {
+        let server = server();
+        let query = query(r"x\.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(msg.header.response_code, dns::response::Rcode::Refused);
+    }
+
+    fn ttls(records: &[dns::ResourceRecord]) -> Vec<u32> {
+        records
+            .iter()
+            .map(|rr| match rr {
+                dns::ResourceRecord::A { ttl, .. }
+                | dns::ResourceRecord::AAAA { ttl, .. }
+                | dns::ResourceRecord::NS { ttl, .. }
+                | dns::ResourceRecord::CAA { ttl, .. }
+                | dns::ResourceRecord::SOA { ttl, .. } => *ttl,
+                _ => panic!("unexpected record: {rr:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_uses_ttls_of_zone() {
+        let server = server();
+        let query = query("test.dyn.example.com", dns::QueryType::A);
+
+        let msg = server.standard_query(query);
+
+        assert_eq!(ttls(&msg.answer), vec![60]);
+        assert_eq!(ttls(&msg.authority), vec![3600, 3600]);
+    }
+
+    #[test]
+    fn it_prefers_ttls_of_entry() {
+        let server = server();
+
+        let msg = server.standard_query(query("shop.dyn.example.com", dns::QueryType::A));
+        assert_eq!(ttls(&msg.answer), vec![5]);
+
+        // the entry only overrides the TTL of its A record
+        let msg = server.standard_query(query("shop.dyn.example.com", dns::QueryType::CAA));
+        assert_eq!(ttls(&msg.answer), vec![60]);
+    }
+
+    #[test]
+    fn it_uses_minimum_as_soa_ttl_by_default() {
+        let server = server();
+
+        let msg = server.standard_query(query("example.net", dns::QueryType::SOA));
+        assert_eq!(ttls(&msg.answer), vec![400]);
+
+        let msg = server.standard_query(query("unknown.example.net", dns::QueryType::A));
+        assert_eq!(ttls(&msg.authority), vec![400]);
+    }
+
+    #[test]
+    fn it_limits_soa_ttl_of_negative_responses_to_minimum() {
+        let mut server = server();
+        let zone = server
+            .zones
+            .get_mut(&DomainName::from("dyn.example.com"))
+            .unwrap();
+        zone.ttl.soa = Some(1000);
+
+        let msg = server.standard_query(query("dyn.example.com", dns::QueryType::SOA));
+        assert_eq!(ttls(&msg.answer), vec![1000]);
+
+        let msg = server.standard_query(query("unknown.dyn.example.com", dns::QueryType::A));
+        assert_eq!(ttls(&msg.authority), vec![400]);
+    }
+
+    #[test]
+    fn it_applies_default_ttl_of_zone_to_soa() {
+        let server = server();
+
+        let msg = server.standard_query(query("dyn.example.com", dns::QueryType::SOA));
+        assert_eq!(ttls(&msg.answer), vec![60]);
+
+        let msg = server.standard_query(query("unknown.dyn.example.com", dns::QueryType::A));
+        assert_eq!(ttls(&msg.authority), vec![60]);
+    }
+
+    fn raw_tcp_query(name: &str) -> Vec<u8> {
+        let header = dns::RawHeader {
+            id: 1234,
+            qr: dns::Qr::Query,
+            opcode: dns::OpCode::StandardQuery,
+            truncated: false,
+            authoritative_answer: false,
+            recursion_desired: false,
+            recursion_available: false,
+            response_code: dns::response::Rcode::NoError,
+            qd_count: 1,
+            an_count: 0,
+            ns_count: 0,
+            ar_count: 0,
+        };
+
+        let mut msg: Vec<u8> = header.into();
+        msg.extend(Vec::<u8>::from(query(name, dns::QueryType::A).question));
+
+        let mut raw_query = (msg.len() as u16).to_be_bytes().to_vec();
+        raw_query.extend(msg);
+        raw_query
+    }
+
+    #[tokio::test]
+    async fn it_limits_concurrent_tcp_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::new(server()).serve_tcp(
+            listener,
+            Arc::new(Semaphore::new(1)),
+            super::TCP_SEND_TIMEOUT,
+        ));
+
+        let idle = TcpStream::connect(addr).await.unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        client.writable().await.unwrap();
+        client
+            .try_write(&raw_tcp_query("test.dyn.example.com"))
+            .unwrap();
+
+        // the only permit is held by the idle connection
+        let response = tokio::time::timeout(Duration::from_millis(200), client.readable()).await;
+        assert!(response.is_err());
+
+        drop(idle);
+        tokio::time::timeout(Duration::from_secs(5), client.readable())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut buf = [0; 512];
+        assert!(client.try_read(&mut buf).unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn it_closes_tcp_connections_that_dont_read() {
+        // small buffers, inherited by accepted connections, fill up quickly
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.set_send_buffer_size(4096).unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(1024).unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::new(server()).serve_tcp(
+            listener,
+            Arc::new(Semaphore::new(1)),
+            Duration::from_millis(100),
+        ));
+
+        // pipeline queries without ever reading the responses until the
+        // server stops accepting them
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.set_recv_buffer_size(4096).unwrap();
+        let mut stalled = socket.connect(addr).await.unwrap();
+        let queries = raw_tcp_query("test.dyn.example.com").repeat(1024);
+        let flood = async { while stalled.write_all(&queries).await.is_ok() {} };
+        let _ = tokio::time::timeout(Duration::from_secs(1), flood).await;
+
+        // the stalled connection must give up its permit
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(&raw_tcp_query("test.dyn.example.com"))
+            .await
+            .unwrap();
+        let mut buf = [0; 512];
+        let len = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(len > 0);
+    }
 }
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+
+use bytes::BufMut;
+
+use crate::dns::{
+    DomainName, LabelPointer, Name, QueryClass, QueryType, Question, ResourceRecord, label_octets,
+};
+
+/// Pointers only have 14 bits to address a previous occurrence of a name,
+/// see [RFC 1035](https://tools.ietf.org/html/rfc1035#section-4.1.4).
+const MAX_POINTER_OFFSET: usize = 0b0011111111111111;
+
+const MAX_CHARACTER_STRING_LEN: usize = 255;
+
+/// Writes the sections of a DNS message and compresses domain names by
+/// replacing already written names or suffixes of them with pointers as
+/// described in [RFC 1035](https://tools.ietf.org/html/rfc1035#section-4.1.4).
+pub struct MessageWriter {
+    buf: Vec<u8>,
+    /// Offsets of all names and suffixes of names written so far
+    names: HashMap<String, u16>,
+    compress: bool,
+}
+
+impl MessageWriter {
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            names: HashMap::new(),
+            compress: true,
+        }
+    }
+
+    /// Creates a writer which never emits pointers, e.g. for encoding a
+    /// single resource record that isn't part of a message.
+    pub fn without_compression() -> Self {
+        Self {
+            compress: false,
+            ..Self::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Drops everything written after `len`, including the names that
+    /// could have been referenced from there.
+    pub fn truncate(&mut self, len: usize) {
+        self.buf.truncate(len);
+        self.names.retain(|_, offset| usize::from(*offset) < len);
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.buf.put_u8(value);
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.buf.put_u16(value);
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.buf.put_u32(value);
+    }
+
+    pub fn put_slice(&mut self, value: &[u8]) {
+        self.buf.put_slice(value);
+    }
+
+    pub fn write_name<S: AsRef<str>>(&mut self, labels: &[S]) {
+        for (i, label) in labels.iter().enumerate() {
+            let suffix = labels[i..]
+                .iter()
+                .map(AsRef::as_ref)
+                .collect::<Vec<_>>()
+                .join(".");
+
+            if self.compress {
+                if let Some(offset) = self.names.get(&suffix) {
+                    let pointer = Name::Pointer(LabelPointer::new(*offset));
+                    self.buf.append(&mut pointer.into());
+                    return;
+                }
+
+                if self.buf.len() <= MAX_POINTER_OFFSET {
+                    self.names.insert(suffix, self.buf.len() as u16);
+                }
+            }
+
+            let label = label_octets(label.as_ref());
+            self.buf.put_u8(label.len() as u8);
+            self.buf.put_slice(&label);
+        }
+
+        // null termination
+        self.buf.put_u8(0);
+    }
+
+    pub fn write_domain_name(&mut self, name: &DomainName) {
+        let labels: Vec<&str> = name.labels().collect();
+        self.write_name(&labels);
+    }
+
+    /// Writes the name without pointers, for RDATA fields that must not be
+    /// compressed.
+    pub fn write_uncompressed_domain_name(&mut self, name: &DomainName) {
+        let compress = std::mem::replace(&mut self.compress, false);
+        self.write_domain_name(name);
+        self.compress = compress;
+    }
+
+    /// Writes `text` as a sequence of <character-string>s, which are
+    /// limited to 255 octets each, see
+    /// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-3.3).
+    pub fn write_character_strings(&mut self, text: &[u8]) {
+        if text.is_empty() {
+            self.put_u8(0);
+            return;
+        }
+
+        for chunk in text.chunks(MAX_CHARACTER_STRING_LEN) {
+            self.put_u8(chunk.len() as u8);
+            self.put_slice(chunk);
+        }
+    }
+
+    pub fn write_question(&mut self, question: &Question) {
+        self.write_name(&question.labels);
+        self.put_u16(question.query_type.into());
+        self.put_u16(question.query_class.into());
+    }
+
+    pub fn write_record(&mut self, rr: &ResourceRecord) {
+        match rr {
+            ResourceRecord::A { name, ttl, addr } => {
+                self.write_record_header(name, QueryType::A, *ttl);
+                self.write_rdata(|w| w.put_u32((*addr).into()));
+            }
+            ResourceRecord::AAAA { name, ttl, addr } => {
+                self.write_record_header(name, QueryType::AAAA, *ttl);
+                self.write_rdata(|w| w.put_slice(&addr.octets()));
+            }
+            ResourceRecord::NS { name, ttl, nsdname } => {
+                self.write_record_header(name, QueryType::NS, *ttl);
+                self.write_rdata(|w| w.write_domain_name(nsdname));
+            }
+            ResourceRecord::CNAME { name, ttl, cname } => {
+                self.write_record_header(name, QueryType::CNAME, *ttl);
+                self.write_rdata(|w| w.write_domain_name(cname));
+            }
+            ResourceRecord::PTR {
+                name,
+                ttl,
+                ptrdname,
+            } => {
+                self.write_record_header(name, QueryType::PTR, *ttl);
+                self.write_rdata(|w| w.write_domain_name(ptrdname));
+            }
+            ResourceRecord::TXT { name, ttl, text } => {
+                self.write_record_header(name, QueryType::TXT, *ttl);
+                self.write_rdata(|w| w.write_character_strings(text.as_bytes()));
+            }
+            ResourceRecord::MX {
+                name,
+                ttl,
+                preference,
+                exchange,
+            } => {
+                self.write_record_header(name, QueryType::MX, *ttl);
+                self.write_rdata(|w| {
+                    w.put_u16(*preference);
+                    w.write_domain_name(exchange);
+                });
+            }
+            ResourceRecord::SRV {
+                name,
+                ttl,
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                self.write_record_header(name, QueryType::SRV, *ttl);
+                self.write_rdata(|w| {
+                    w.put_u16(*priority);
+                    w.put_u16(*weight);
+                    w.put_u16(*port);
+                    // the target must not be compressed, see
+                    // https://www.rfc-editor.org/rfc/rfc2782
+                    w.write_uncompressed_domain_name(target);
+                });
+            }
+            ResourceRecord::CAA {
+                name,
+                ttl,
+                flags,
+                tag,
+                value,
+            } => {
+                self.write_record_header(name, QueryType::CAA, *ttl);
+                self.write_rdata(|w| {
+                    w.put_u8(*flags);
+                    w.put_u8(tag.len() as u8);
+                    w.put_slice(tag.as_bytes());
+                    // the value takes up the rest of the RDATA
+                    w.put_slice(value.as_bytes());
+                });
+            }
+            ResourceRecord::SOA { ttl, soa } => {
+                self.write_domain_name(&soa.mname);
+                self.put_u16(QueryType::SOA.into());
+                self.put_u16(QueryClass::IN.into());
+                self.put_u32(*ttl);
+                self.write_rdata(|w| {
+                    w.write_domain_name(&soa.mname);
+                    w.write_domain_name(&soa.rname);
+                    w.put_u32(soa.serial);
+                    w.put_u32(soa.refresh);
+                    w.put_u32(soa.retry);
+                    w.put_u32(soa.expire);
+                    w.put_u32(soa.minimum);
+                });
+            }
+        }
+    }
+
+    fn write_record_header(&mut self, name: &Name, rtype: QueryType, ttl: u32) {
+        match name {
+            Name::Labels(labels) => self.write_name(labels),
+            Name::Pointer(_) => self.buf.append(&mut name.clone().into()),
+        }
+        self.put_u16(rtype.into());
+        self.put_u16(QueryClass::IN.into());
+        self.put_u32(ttl);
+    }
+
+    /// Writes the RDATA produced by `write` prefixed with its length.
+    fn write_rdata(&mut self, write: impl FnOnce(&mut Self)) {
+        let len_offset = self.buf.len();
+        self.put_u16(0);
+
+        write(self);
+
+        let rdata_len = (self.buf.len() - len_offset - 2) as u16;
+        self.buf[len_offset..len_offset + 2].copy_from_slice(&rdata_len.to_be_bytes());
+    }
+}
+
+impl Default for MessageWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<MessageWriter> for Vec<u8> {
+    fn from(writer: MessageWriter) -> Self {
+        writer.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::dns::StartOfAuthority;
+
+    fn labels(name: &str) -> Vec<String> {
+        name.split('.').map(String::from).collect()
+    }
+
+    #[test]
+    fn it_compresses_repeated_names() {
+        let mut writer = MessageWriter::new();
+
+        writer.write_name(&labels("test.dyn.example.com"));
+        writer.write_name(&labels("test.dyn.example.com"));
+
+        let raw: Vec<u8> = writer.into();
+        assert_eq!(raw.len(), 22 + 2);
+        assert_eq!(&raw[22..], &[0b11000000, 0]);
+    }
+
+    #[test]
+    fn it_compresses_suffixes() {
+        let mut writer = MessageWriter::new();
+
+        writer.write_name(&labels("test.dyn.example.com"));
+        writer.write_name(&labels("other.dyn.example.com"));
+
+        let raw: Vec<u8> = writer.into();
+        assert_eq!(&raw[22..], b"\x05other\xc0\x05");
+    }
+
+    #[test]
+    fn it_does_not_compress_without_compression() {
+        let mut writer = MessageWriter::without_compression();
+
+        writer.write_name(&labels("test.dyn.example.com"));
+        writer.write_name(&labels("test.dyn.example.com"));
+
+        let raw: Vec<u8> = writer.into();
+        assert_eq!(raw.len(), 2 * 22);
+    }
+
+    #[test]
+    fn it_forgets_truncated_names() {
+        let mut writer = MessageWriter::new();
+
+        writer.write_name(&labels("example.com"));
+        let len = writer.len();
+        writer.write_name(&labels("test.dyn.example.com"));
+        writer.truncate(len);
+        writer.write_name(&labels("dyn.example.com"));
+
+        let raw: Vec<u8> = writer.into();
+        assert_eq!(&raw[len..], b"\x03dyn\xc0\x00");
+    }
+
+    #[test]
+    fn it_compresses_names_in_soa_rdata() {
+        let mut writer = MessageWriter::new();
+
+        let soa = StartOfAuthority {
+            mname: DomainName::from("dyn.example.com"),
+            rname: DomainName::from("postmaster.example.com"),
+            serial: 1,
+            refresh: 2,
+            retry: 3,
+            expire: 4,
+            minimum: 5,
+        };
+        writer.write_question(&Question {
+            labels: labels("dyn.example.com"),
+            name: "dyn.example.com".to_string(),
+            query_type: QueryType::SOA,
+            query_class: QueryClass::IN,
+        });
+        writer.write_record(&ResourceRecord::SOA { ttl: 5, soa });
+
+        let raw: Vec<u8> = writer.into();
+        // question (17 + 4) + owner pointer (2) + type, class, ttl (8)
+        let rdata = &raw[31..];
+        assert_eq!(&rdata[0..2], &[0, 2 + 13 + 5 * 4]);
+        // mname
+        assert_eq!(&rdata[2..4], &[0b11000000, 0]);
+        // rname
+        assert_eq!(&rdata[4..17], b"\x0apostmaster\xc0\x04");
+    }
+
+    #[test]
+    fn it_writes_rdata_length() {
+        let mut writer = MessageWriter::without_compression();
+
+        writer.write_record(&ResourceRecord::A {
+            name: Name::Labels(labels("test")),
+            ttl: 15,
+            addr: Ipv4Addr::new(1, 2, 3, 4),
+        });
+
+        let raw: Vec<u8> = writer.into();
+        assert_eq!(&raw[14..], &[0, 4, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn it_compresses_ns_rdata() {
+        let mut writer = MessageWriter::new();
+
+        writer.write_record(&ResourceRecord::NS {
+            name: Name::Labels(labels("dyn.example.com")),
+            ttl: 15,
+            nsdname: DomainName::from("ns.dyn.example.com"),
+        });
+
+        let raw: Vec<u8> = writer.into();
+        // owner (17) + type, class, ttl (8)
+        assert_eq!(&raw[25..], b"\x00\x05\x02ns\xc0\x00");
+    }
+
+    #[test]
+    fn it_splits_long_txt_data_into_character_strings() {
+        let mut writer = MessageWriter::without_compression();
+
+        writer.write_record(&ResourceRecord::TXT {
+            name: Name::Labels(labels("test")),
+            ttl: 15,
+            text: "a".repeat(300),
+        });
+
+        let raw: Vec<u8> = writer.into();
+        // owner (6) + type, class, ttl (8)
+        let rdata = &raw[14..];
+        assert_eq!(&rdata[0..2], &302u16.to_be_bytes());
+        assert_eq!(rdata[2], 255);
+        assert_eq!(rdata[2 + 256], 45);
+        assert_eq!(rdata.len(), 2 + 302);
+    }
+
+    #[test]
+    fn it_writes_empty_txt_data() {
+        let mut writer = MessageWriter::without_compression();
+
+        writer.write_record(&ResourceRecord::TXT {
+            name: Name::Labels(labels("test")),
+            ttl: 15,
+            text: String::new(),
+        });
+
+        let raw: Vec<u8> = writer.into();
+        assert_eq!(&raw[14..], &[0, 1, 0]);
+    }
+
+    #[test]
+    fn it_writes_mx_rdata() {
+        let mut writer = MessageWriter::new();
+
+        writer.write_record(&ResourceRecord::MX {
+            name: Name::Labels(labels("dyn.example.com")),
+            ttl: 15,
+            preference: 10,
+            exchange: DomainName::from("mail.dyn.example.com"),
+        });
+
+        let raw: Vec<u8> = writer.into();
+        // owner (17) + type, class, ttl (8)
+        assert_eq!(&raw[25..], b"\x00\x09\x00\x0a\x04mail\xc0\x00");
+    }
+
+    #[test]
+    fn it_does_not_compress_srv_target() {
+        let mut writer = MessageWriter::new();
+
+        writer.write_record(&ResourceRecord::SRV {
+            name: Name::Labels(labels("_sip._tcp.dyn.example.com")),
+            ttl: 15,
+            priority: 1,
+            weight: 2,
+            port: 5060,
+            target: DomainName::from("dyn.example.com"),
+        });
+
+        let raw: Vec<u8> = writer.into();
+        // owner (27) + type, class, ttl (8)
+        let rdata = &raw[35..];
+        assert_eq!(&rdata[..8], &[0, 6 + 17, 0, 1, 0, 2, 0x13, 0xc4]);
+        assert_eq!(&rdata[8..], b"\x03dyn\x07example\x03com\x00");
+    }
+}
@@ -1,11 +1,8 @@
 pub mod dns;
+pub mod net;
 mod parser;
 pub mod settings;
 mod storage;
 pub mod web;
 
-use std::sync::{Arc, Mutex};
-
-pub use storage::Storage;
-
-pub type SharedStorage = Arc<Mutex<Storage>>;
+pub use storage::{SharedStorage, Storage};
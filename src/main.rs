@@ -1,47 +1,70 @@
 use koppeln::settings::Settings;
-use koppeln::{Storage, dns, web};
+use koppeln::{SharedStorage, Storage, dns, net, web};
 
 use config::ConfigError;
 use env_logger::Env;
 
+use std::fmt;
+use std::io;
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+
+#[derive(thiserror::Error)]
+enum Error {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Bind(#[from] net::BindError),
+    #[error("DNS server failed: {0}")]
+    Dns(#[from] io::Error),
+    #[error(transparent)]
+    Task(#[from] tokio::task::JoinError),
+}
+
+// errors returned from `main` are printed with their `Debug` representation
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
 
 #[tokio::main]
-async fn main() -> Result<(), ConfigError> {
+async fn main() -> Result<(), Error> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
     let settings = Settings::load()?;
     log::debug!("Settings loaded:\n{:?}", settings);
 
-    let storage = Arc::new(Mutex::new(Storage::new(
-        settings.soa.mname.clone(),
-        settings.addresses,
-    )));
-
-    let web_server_address = SocketAddr::from((settings.web_address, settings.web_port));
-    let update_server = tokio::spawn(web::create_update_server(
-        web_server_address,
-        Arc::clone(&storage),
-    ));
-    log::info!(
-        "HTTP server now listening on: {ip}:{port}",
-        ip = settings.web_address,
-        port = settings.web_port
-    );
+    // all sockets are bound up front, so that a single address that can't
+    // be bound stops the startup instead of leaving a partial server behind
+    let web_listeners = bind_all(&settings.web_listen, net::bind_tcp)?;
+    let dns_udp_sockets = bind_all(&settings.dns_listen, net::bind_udp)?;
+    let dns_tcp_listeners = bind_all(&settings.dns_listen, net::bind_tcp)?;
+    // TODO: drop privileges
+
+    let storage = SharedStorage::new(Storage::new(settings.storage_zones()));
+
+    let update_server = tokio::spawn(web::create_update_server(web_listeners, storage.clone()));
 
     let dns_server = dns::Server {
-        soa: settings.soa,
+        zones: settings.dns_zones(),
+        any_query: settings.any_query,
         storage,
-        listen_addr: settings.dns_address,
-        listen_port: settings.dns_port,
     };
 
-    let dns_server = tokio::spawn(dns_server.run());
+    let dns_server = tokio::spawn(dns_server.run(dns_udp_sockets, dns_tcp_listeners));
 
-    futures::future::try_join(update_server, dns_server)
-        .await
-        .unwrap();
+    // both servers run forever, unless one of them fails
+    tokio::select! {
+        res = update_server => res??,
+        res = dns_server => res??,
+    }
 
     Ok(())
 }
+
+fn bind_all<T>(
+    addresses: &[SocketAddr],
+    bind: impl Fn(SocketAddr) -> Result<T, net::BindError>,
+) -> Result<Vec<T>, net::BindError> {
+    addresses.iter().map(|&addr| bind(addr)).collect()
+}
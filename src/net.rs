@@ -0,0 +1,90 @@
+use std::io;
+use std::net::SocketAddr;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, UdpSocket};
+
+/// Maximum number of pending TCP connections.
+const TCP_BACKLOG: i32 = 1024;
+
+#[derive(Debug, thiserror::Error)]
+#[error("failed to bind {addr} ({protocol}): {source}")]
+pub struct BindError {
+    pub addr: SocketAddr,
+    pub protocol: &'static str,
+    #[source]
+    pub source: io::Error,
+}
+
+pub fn bind_udp(addr: SocketAddr) -> Result<UdpSocket, BindError> {
+    let bind = || {
+        let socket = bind(addr, Type::DGRAM, Protocol::UDP)?;
+        UdpSocket::from_std(socket.into())
+    };
+
+    bind().map_err(|source| BindError {
+        addr,
+        protocol: "UDP",
+        source,
+    })
+}
+
+pub fn bind_tcp(addr: SocketAddr) -> Result<TcpListener, BindError> {
+    let bind = || {
+        let socket = bind(addr, Type::STREAM, Protocol::TCP)?;
+        socket.listen(TCP_BACKLOG)?;
+        TcpListener::from_std(socket.into())
+    };
+
+    bind().map_err(|source| BindError {
+        addr,
+        protocol: "TCP",
+        source,
+    })
+}
+
+fn bind(addr: SocketAddr, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+
+    // IPv6 sockets would accept IPv4 traffic as well on most systems, which
+    // prevents binding `0.0.0.0` and `[::]` side by side
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // allows restarting the server while old connections are in TIME_WAIT
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_reports_address_of_failed_bind() {
+        let socket = bind_udp(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let err = bind_udp(addr).unwrap_err();
+
+        assert_eq!(err.addr, addr);
+        assert_eq!(err.source.kind(), io::ErrorKind::AddrInUse);
+        assert!(
+            err.to_string()
+                .starts_with(&format!("failed to bind {addr} (UDP)"))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_binds_udp_and_tcp_to_same_port() {
+        let listener = bind_tcp(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        assert!(bind_udp(addr).is_ok());
+    }
+}
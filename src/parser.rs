@@ -4,22 +4,22 @@ use nom::Parser;
 use nom::{
     IResult,
     bits::{bits, complete::take},
-    bytes::complete::tag,
-    character::complete::{alpha1, alphanumeric1, char},
-    combinator::{all_consuming, map, map_res, peek, recognize},
-    multi::{length_value, many_till, separated_list0},
-    number::complete::{be_u8, be_u16},
-    sequence::pair,
+    combinator::{all_consuming, map},
+    error::{ErrorKind, make_error},
+    multi::{count, length_data, many0},
+    number::complete::{be_u8, be_u16, be_u32},
 };
 
-use std::str;
-
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("not enough data")]
     Incomplete,
     #[error("failed to parse payload")]
     Parser,
+    /// Responses are never answered, otherwise two servers could keep
+    /// answering each other's responses forever
+    #[error("message is a response")]
+    Response,
 }
 
 impl<F> From<nom::Err<F>> for Error {
@@ -33,26 +33,17 @@ impl<F> From<nom::Err<F>> for Error {
 
 pub fn dns_query(input: &[u8]) -> Result<dns::Request, Error> {
     let (rem, header) = dns_header(input)?;
+    if header.qr == dns::Qr::Response {
+        return Err(Error::Response);
+    }
 
     let request = match header.opcode {
-        dns::OpCode::StandardQuery => {
-            // TODO: treat query with qdcount > 1 as format error (code 1) according to:
-            // https://www.rfc-editor.org/rfc/rfc9619#name-updates-to-rfc-1035
-
-            let (_rem, question) = dns_question(rem)?;
-            // TODO: we should check rem for remainding data, which would also
-            // indicate a format error
-
-            // TODO impl From<RawHeader> for request::Header
-            let header = request::Header {
-                id: header.id,
-                truncated: header.truncated,
-                recursion_desired: header.recursion_desired,
-                qd_count: header.qd_count,
-            };
-            let query = dns::request::StandardQuery { header, question };
-            dns::Request::StandardQuery(query)
-        }
+        dns::OpCode::StandardQuery => match dns_standard_query(input, rem, &header) {
+            Ok(query) => dns::Request::StandardQuery(query),
+            // the header is known, so we are able to answer with FORMERR
+            Err(Error::Parser) => dns::Request::Malformed(header),
+            Err(err) => return Err(err),
+        },
         dns::OpCode::InversQuery | dns::OpCode::ServerStatusRequest | dns::OpCode::Reserved(_) => {
             dns::Request::Unsupported(header)
         }
@@ -61,6 +52,50 @@ pub fn dns_query(input: &[u8]) -> Result<dns::Request, Error> {
     Ok(request)
 }
 
+/// Parses the sections following the `header` of a standard query, `message`
+/// is the whole message.
+fn dns_standard_query(
+    message: &[u8],
+    input: &[u8],
+    header: &dns::RawHeader,
+) -> Result<request::StandardQuery, Error> {
+    // a query has to contain exactly one question, see:
+    // https://www.rfc-editor.org/rfc/rfc9619#name-updates-to-rfc-1035
+    if header.qd_count != 1 {
+        return Err(Error::Parser);
+    }
+
+    let (rem, question) = dns_question(message).parse(input)?;
+
+    // queries are not expected to carry answer or authority records,
+    // but we have to skip them to get to the additional section
+    let skipped = usize::from(header.an_count) + usize::from(header.ns_count);
+    let (rem, _) = count(dns_resource_record(message), skipped).parse(rem)?;
+    let (rem, additional) =
+        count(dns_resource_record(message), usize::from(header.ar_count)).parse(rem)?;
+
+    // data after the last record means the counts are wrong
+    if !rem.is_empty() {
+        return Err(Error::Parser);
+    }
+
+    let edns = dns_edns(additional)?;
+
+    // TODO impl From<RawHeader> for request::Header
+    let header = request::Header {
+        id: header.id,
+        truncated: header.truncated,
+        recursion_desired: header.recursion_desired,
+        qd_count: header.qd_count,
+    };
+
+    Ok(request::StandardQuery {
+        header,
+        question,
+        edns,
+    })
+}
+
 pub fn dns_header(input: &[u8]) -> IResult<&[u8], dns::RawHeader> {
     let mut parser = (
         be_u16,
@@ -80,13 +115,16 @@ pub fn dns_header(input: &[u8]) -> IResult<&[u8], dns::RawHeader> {
         be_u16,
     );
 
-    let (input, (id, (_, opcode, _, tc, rd, ra, _, rcode), qd_count, _, _, _)) =
-        parser.parse(input)?;
+    let (
+        input,
+        (id, (qr, opcode, _, tc, rd, ra, _, rcode), qd_count, an_count, ns_count, ar_count),
+    ) = parser.parse(input)?;
 
     Ok((
         input,
         dns::RawHeader {
             id,
+            qr: u16::from(qr).into(),
             opcode: opcode.into(),
             authoritative_answer: false,
             truncated: tc != 0,
@@ -94,53 +132,181 @@ pub fn dns_header(input: &[u8]) -> IResult<&[u8], dns::RawHeader> {
             recursion_available: ra != 0,
             response_code: rcode.into(),
             qd_count,
-            an_count: 0,
-            ar_count: 0,
-            ns_count: 0,
+            an_count,
+            ar_count,
+            ns_count,
         },
     ))
 }
-pub fn dns_question(input: &[u8]) -> IResult<&[u8], dns::Question> {
-    let mut parser = (dns_labels, be_u16, be_u16);
 
-    let (rem, (labels, qtype, qclass)) = parser.parse(input)?;
-    let name = labels.join(".");
+/// Parses a question, `message` is the whole message the question is part
+/// of, which is needed to resolve compressed names.
+pub fn dns_question<'a>(
+    message: &'a [u8],
+) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], dns::Question> {
+    move |input| {
+        let mut parser = (dns_name(message), be_u16, be_u16);
+
+        let (rem, (labels, qtype, qclass)) = parser.parse(input)?;
+        let name = labels.join(".");
+
+        Ok((
+            rem,
+            dns::Question {
+                labels,
+                name,
+                query_type: qtype.into(),
+                query_class: qclass.into(),
+            },
+        ))
+    }
+}
+
+struct RawResourceRecord<'a> {
+    labels: Vec<String>,
+    rtype: dns::QueryType,
+    class: u16,
+    ttl: u32,
+    rdata: &'a [u8],
+}
+
+/// Parses a resource record without interpreting its RDATA, see
+/// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-4.1.3).
+fn dns_resource_record<'a>(
+    message: &'a [u8],
+) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], RawResourceRecord<'a>> {
+    move |input| {
+        let mut parser = (
+            dns_name(message),
+            be_u16,
+            be_u16,
+            be_u32,
+            length_data(be_u16),
+        );
+
+        let (rem, (labels, rtype, class, ttl, rdata)) = parser.parse(input)?;
+
+        Ok((
+            rem,
+            RawResourceRecord {
+                labels,
+                rtype: rtype.into(),
+                class,
+                ttl,
+                rdata,
+            },
+        ))
+    }
+}
+
+/// Extracts the OPT pseudo-record from the additional section as described
+/// in [RFC 6891](https://www.rfc-editor.org/rfc/rfc6891#section-6.1.1).
+fn dns_edns(additional: Vec<RawResourceRecord>) -> Result<Option<dns::Edns>, Error> {
+    let mut opt_records = additional
+        .into_iter()
+        .filter(|rr| rr.rtype == dns::QueryType::OPT);
+
+    let Some(opt) = opt_records.next() else {
+        return Ok(None);
+    };
+
+    // a message must not contain more than one OPT record and the OPT
+    // record must be owned by the root domain
+    if opt_records.next().is_some() || !opt.labels.is_empty() {
+        return Err(Error::Parser);
+    }
+
+    let (_, options) = all_consuming(many0(edns_option)).parse(opt.rdata)?;
+
+    Ok(Some(dns::Edns {
+        udp_payload_size: opt.class,
+        extended_rcode: (opt.ttl >> 24) as u8,
+        version: (opt.ttl >> 16) as u8,
+        dnssec_ok: opt.ttl & 0b1000000000000000 != 0,
+        options,
+    }))
+}
+
+fn edns_option(input: &[u8]) -> IResult<&[u8], dns::EdnsOption> {
+    let mut parser = (be_u16, length_data(be_u16));
+
+    let (rem, (code, data)) = parser.parse(input)?;
 
     Ok((
         rem,
-        dns::Question {
-            labels,
-            name,
-            query_type: qtype.into(),
-            query_class: qclass.into(),
+        dns::EdnsOption {
+            code,
+            data: data.to_vec(),
         },
     ))
 }
 
-fn dns_labels(input: &[u8]) -> IResult<&[u8], Vec<String>> {
-    let mut parser = many_till(dns_label, tag("\0"));
-
-    let (rem, (labels, _)) = parser.parse(input)?;
-
-    Ok((rem, labels))
+/// Maximum length of a domain name in wire format, see
+/// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-2.3.4).
+const MAX_NAME_LEN: usize = 255;
+
+const POINTER_MASK: u8 = 0b11000000;
+
+/// Parses a sequence of labels and follows compression pointers into
+/// `message` as described in [RFC 1035](https://tools.ietf.org/html/rfc1035#section-4.1.4).
+///
+/// A pointer must point to an offset before the start of the name and
+/// before every previously followed pointer. This prevents loops and
+/// implies that all pointers stay within the message.
+fn dns_name<'a>(message: &'a [u8]) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], Vec<String>> {
+    move |input| {
+        let mut labels = vec![];
+        let mut name_len = 0;
+        // remaining input after the first pointer, if any
+        let mut rem_after_pointer = None;
+        // the upper bound for the next pointer
+        let mut max_offset = message.len() - input.len();
+        let mut current = input;
+
+        loop {
+            let (rem, len) = be_u8(current)?;
+
+            match len & POINTER_MASK {
+                0 if len == 0 => return Ok((rem_after_pointer.unwrap_or(rem), labels)),
+                0 => {
+                    let (rem, label) = dns_label(current)?;
+
+                    name_len += 1 + label.len();
+                    // the null label is part of the name, too
+                    if name_len + 1 > MAX_NAME_LEN {
+                        return Err(nom::Err::Error(make_error(current, ErrorKind::TooLarge)));
+                    }
+
+                    labels.push(label);
+                    current = rem;
+                }
+                POINTER_MASK => {
+                    let (rem, low) = be_u8(rem)?;
+                    let offset = usize::from(len & !POINTER_MASK) << 8 | usize::from(low);
+
+                    if offset >= max_offset {
+                        return Err(nom::Err::Error(make_error(current, ErrorKind::Verify)));
+                    }
+
+                    rem_after_pointer.get_or_insert(rem);
+                    max_offset = offset;
+                    current = &message[offset..];
+                }
+                // the label types 0b01 and 0b10 are reserved
+                _ => return Err(nom::Err::Error(make_error(current, ErrorKind::Tag))),
+            }
+        }
+    }
 }
 
-/// Implemented as described in [RFC 1035](https://tools.ietf.org/html/rfc1035#section-2.3.1)
+/// Parses a single label. As described in
+/// [RFC 2181](https://www.rfc-editor.org/rfc/rfc2181#section-11) a label may
+/// contain any octet, so the hostname syntax of
+/// [RFC 1035](https://tools.ietf.org/html/rfc1035#section-2.3.1) is not
+/// enforced here. Octets that can't be part of a plain label are escaped
+/// like in master files, see [`dns::escape_label`].
 fn dns_label(input: &[u8]) -> IResult<&[u8], String> {
-    // TODO: add support for message compression as desribed in https://tools.ietf.org/html/rfc1035#section-4.1.4
-    let mut parser = map(
-        map_res(
-            length_value(
-                be_u8,
-                all_consuming(recognize(pair(
-                    peek(alpha1), // a label must start with an ASCII letter
-                    separated_list0(char('-'), alphanumeric1),
-                ))),
-            ),
-            str::from_utf8,
-        ),
-        |s| s.to_owned(),
-    );
+    let mut parser = map(length_data(be_u8), dns::escape_label);
 
     let (rem, label) = parser.parse(input)?;
 
@@ -246,32 +412,50 @@ mod tests {
         assert_eq!(label, String::from("test-hyphen"));
     }
 
-    // #[test]
-    // fn test_parse_leading_digit_label() {
-    //     let raw_header = b"\x050test";
+    #[test]
+    fn test_parse_leading_digit_label() {
+        let raw_header = b"\x050test";
 
-    //     let (_, label) = dns_label(raw_header).unwrap();
+        let (_, label) = dns_label(raw_header).unwrap();
 
-    //     assert_eq!(label, String::from("0test"));
-    // }
+        assert_eq!(label, String::from("0test"));
+    }
 
-    // #[test]
-    // fn test_parse_leading_hyphen_label() {
-    //     let raw_header = b"\x05-test";
+    #[test]
+    fn test_parse_leading_hyphen_label() {
+        let raw_header = b"\x05-test";
 
-    //     let (_, label) = dns_label(raw_header).unwrap();
+        let (_, label) = dns_label(raw_header).unwrap();
 
-    //     assert_eq!(label, String::from(""));
-    // }
+        assert_eq!(label, String::from("-test"));
+    }
 
-    // #[test]
-    // fn test_parse_tailing_hyphen_label() {
-    //     let raw_header = b"\x05test-";
+    #[test]
+    fn test_parse_tailing_hyphen_label() {
+        let raw_header = b"\x05test-";
 
-    //     let (_, label) = dns_label(raw_header).unwrap();
+        let (_, label) = dns_label(raw_header).unwrap();
+
+        assert_eq!(label, String::from("test-"));
+    }
+
+    #[test]
+    fn test_parse_underscore_label() {
+        let raw_header = b"\x0f_acme-challenge";
+
+        let (_, label) = dns_label(raw_header).unwrap();
 
-    //     assert_eq!(label, String::from(""));
-    // }
+        assert_eq!(label, String::from("_acme-challenge"));
+    }
+
+    #[test]
+    fn test_parse_binary_label() {
+        let raw_header = b"\x05a.b\\\xff";
+
+        let (_, label) = dns_label(raw_header).unwrap();
+
+        assert_eq!(label, String::from("a\\.b\\\\\\255"));
+    }
 
     #[test]
     fn test_parse_alphabetic_label() {
@@ -286,7 +470,7 @@ mod tests {
     fn test_parse_labels() {
         let raw_labels = b"\x04\x74\x65\x73\x74\x03\x64\x79\x6e\x07\x65\x78\x61\x6d\x70\x6c\x65\x03\x63\x6f\x6d\x00";
 
-        let (_, labels) = dns_labels(raw_labels).unwrap();
+        let (_, labels) = dns_name(raw_labels)(raw_labels).unwrap();
 
         assert_eq!(labels[0], String::from("test"));
         assert_eq!(labels[1], String::from("dyn"));
@@ -296,7 +480,7 @@ mod tests {
     fn test_parse_question() {
         let raw_question = b"\x04\x74\x65\x73\x74\x03\x64\x79\x6e\x07\x65\x78\x61\x6d\x70\x6c\x65\x03\x63\x6f\x6d\x00\x00\x01\x00\x01";
 
-        let (_, question) = dns_question(raw_question).unwrap();
+        let (_, question) = dns_question(raw_question)(raw_question).unwrap();
         assert_eq!(question.labels[0], String::from("test"));
         assert_eq!(question.query_type, dns::QueryType::A);
         assert_eq!(question.query_class, dns::QueryClass::IN);
@@ -307,6 +491,160 @@ mod tests {
         let raw_data = b"\xf4\x4c\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x04\x74\x65\x73\x74\x03\x64\x79\x6e\x07\x65\x78\x61\x6d\x70\x6c\x65\x03\x63\x6f\x6d\x00\x00\x01\x00\x01";
 
         let (input, _) = dns_header(raw_data).unwrap();
-        let (_, _) = dns_question(input).unwrap();
+        let (_, _) = dns_question(raw_data)(input).unwrap();
+    }
+
+    #[test]
+    fn test_parse_query_with_edns() {
+        let raw_data = b"\xf4\x4c\x01\x00\x00\x01\x00\x00\x00\x00\x00\x01\x04test\x03dyn\x07example\x03com\x00\x00\x01\x00\x01\x00\x00\x29\x04\xd0\x01\x00\x80\x00\x00\x0c\x00\x0a\x00\x08\x01\x02\x03\x04\x05\x06\x07\x08";
+
+        let dns::Request::StandardQuery(query) = dns_query(raw_data).unwrap() else {
+            panic!("expected a standard query");
+        };
+
+        let edns = query.edns.unwrap();
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert_eq!(edns.extended_rcode, 1);
+        assert_eq!(edns.version, 0);
+        assert!(edns.dnssec_ok);
+        assert_eq!(
+            edns.options,
+            vec![dns::EdnsOption {
+                code: 10,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_query_without_edns() {
+        let raw_data = b"\xf4\x4c\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x04test\x03dyn\x07example\x03com\x00\x00\x01\x00\x01";
+
+        let dns::Request::StandardQuery(query) = dns_query(raw_data).unwrap() else {
+            panic!("expected a standard query");
+        };
+
+        assert_eq!(query.edns, None);
+    }
+
+    #[test]
+    fn test_parse_query_with_multiple_opt_records() {
+        let raw_data = b"\xf4\x4c\x01\x00\x00\x01\x00\x00\x00\x00\x00\x02\x04test\x03dyn\x07example\x03com\x00\x00\x01\x00\x01\x00\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x00\x00\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x00";
+
+        assert!(matches!(
+            dns_query(raw_data).unwrap(),
+            dns::Request::Malformed(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_query_without_question() {
+        let raw_data = b"\xf4\x4c\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+
+        let dns::Request::Malformed(header) = dns_query(raw_data).unwrap() else {
+            panic!("expected a malformed request");
+        };
+
+        assert_eq!(header.id, 0xf44c);
+    }
+
+    #[test]
+    fn test_parse_query_with_multiple_questions() {
+        let raw_data = b"\xf4\x4c\x01\x00\x00\x02\x00\x00\x00\x00\x00\x00\x04test\x03dyn\x07example\x03com\x00\x00\x01\x00\x01\xc0\x0c\x00\x1c\x00\x01";
+
+        assert!(matches!(
+            dns_query(raw_data).unwrap(),
+            dns::Request::Malformed(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_query_with_trailing_data() {
+        let raw_data = b"\xf4\x4c\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x04test\x03dyn\x07example\x03com\x00\x00\x01\x00\x01\xde\xad";
+
+        assert!(matches!(
+            dns_query(raw_data).unwrap(),
+            dns::Request::Malformed(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_query_with_invalid_name() {
+        let raw_data =
+            b"\xf4\x4c\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x04test\xc0\x0c\x00\x01\x00\x01";
+
+        assert!(matches!(
+            dns_query(raw_data).unwrap(),
+            dns::Request::Malformed(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_compressed_name() {
+        let raw_message = b"\x03dyn\x07example\x03com\x00\x04test\xc0\x00\x00\x01";
+
+        let (rem, labels) = dns_name(raw_message)(&raw_message[17..]).unwrap();
+
+        assert_eq!(labels, vec!["test", "dyn", "example", "com"]);
+        assert_eq!(rem, b"\x00\x01");
+    }
+
+    #[test]
+    fn test_parse_nested_compressed_name() {
+        let raw_message = b"\x07example\x03com\x00\x03dyn\xc0\x00\x04test\xc0\x0d";
+
+        let (rem, labels) = dns_name(raw_message)(&raw_message[19..]).unwrap();
+
+        assert_eq!(labels, vec!["test", "dyn", "example", "com"]);
+        assert!(rem.is_empty());
+    }
+
+    #[test]
+    fn test_parse_compressed_name_pointer_loop() {
+        let raw_message = b"\x04test\xc0\x00";
+
+        assert!(dns_name(raw_message)(raw_message).is_err());
+    }
+
+    #[test]
+    fn test_parse_compressed_name_forward_pointer() {
+        let raw_message = b"\x04test\xc0\x08\x03com\x00";
+
+        assert!(dns_name(raw_message)(raw_message).is_err());
+    }
+
+    #[test]
+    fn test_parse_compressed_name_pointer_out_of_range() {
+        let raw_message = b"\x04test\xc0\xff";
+
+        assert!(dns_name(raw_message)(raw_message).is_err());
+    }
+
+    #[test]
+    fn test_parse_name_too_long() {
+        let raw_message: Vec<u8> = (0..5)
+            .flat_map(|_| {
+                let mut label = vec![63];
+                label.extend([b'a'; 63]);
+                label
+            })
+            .chain([0])
+            .collect();
+
+        assert!(dns_name(&raw_message)(&raw_message).is_err());
+    }
+
+    #[test]
+    fn test_parse_query_with_compressed_additional_record() {
+        // the additional section contains an A record whose owner points to
+        // the question name
+        let raw_data = b"\xf4\x4c\x01\x00\x00\x01\x00\x00\x00\x00\x00\x01\x04test\x03dyn\x07example\x03com\x00\x00\x01\x00\x01\xc0\x0c\x00\x01\x00\x01\x00\x00\x00\x0f\x00\x04\x01\x02\x03\x04";
+
+        let dns::Request::StandardQuery(query) = dns_query(raw_data).unwrap() else {
+            panic!("expected a standard query");
+        };
+
+        assert_eq!(query.question.name, "test.dyn.example.com");
+        assert_eq!(query.edns, None);
     }
 }
@@ -1,25 +1,70 @@
 use std::collections::HashMap;
 use std::env;
-use std::net;
+use std::net::{IpAddr, SocketAddr};
 
 use config::{Config, ConfigError, Environment, File};
 use serde::{Deserialize, Serialize};
 
 use crate::dns;
 use crate::dns::DomainName;
-use crate::storage::SubDomainEntry;
+use crate::storage::{CaaProperty, SubDomainEntry, SubDomains, Ttls};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
-    pub dns_address: net::IpAddr,
-    pub dns_port: u16,
+    /// Sockets of the DNS server, each one is served via UDP and TCP.
+    /// Defaults to `0.0.0.0:53`.
+    #[serde(default)]
+    pub dns_listen: Vec<SocketAddr>,
+    /// Sockets of the HTTP server. There is no default, so the update API
+    /// is never exposed by accident.
+    #[serde(default)]
+    pub web_listen: Vec<SocketAddr>,
 
-    pub web_address: net::IpAddr,
-    pub web_port: u16,
+    // replaced by `dns_listen` and `web_listen`, but still accepted
+    #[serde(default, skip_serializing)]
+    dns_address: Option<IpAddr>,
+    #[serde(default, skip_serializing)]
+    dns_port: Option<u16>,
+    #[serde(default, skip_serializing)]
+    web_address: Option<IpAddr>,
+    #[serde(default, skip_serializing)]
+    web_port: Option<u16>,
 
     pub soa: dns::StartOfAuthority,
+    #[serde(default)]
+    pub name_servers: Vec<DomainName>,
+    #[serde(default)]
+    pub any_query: dns::server::AnyQuery,
+    /// CAA records of the zone apex
+    #[serde(default)]
+    pub caa: Vec<CaaProperty>,
+    /// `in-addr.arpa` or `ip6.arpa` zones whose PTR records are generated
+    /// from the addresses of the hosts
+    #[serde(default)]
+    pub reverse_zones: Vec<DomainName>,
+    /// TTLs of all records, unless overridden by their host
+    #[serde(default)]
+    pub ttl: Ttls,
 
     pub addresses: HashMap<DomainName, SubDomainEntry>,
+
+    /// Additional zones, each with its own SOA, name servers and hosts
+    #[serde(default)]
+    pub zones: Vec<ZoneSettings>,
+}
+
+/// A zone besides the one configured at the top level.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneSettings {
+    pub soa: dns::StartOfAuthority,
+    #[serde(default)]
+    pub name_servers: Vec<DomainName>,
+    #[serde(default)]
+    pub caa: Vec<CaaProperty>,
+    #[serde(default)]
+    pub ttl: Ttls,
+    #[serde(default)]
+    pub addresses: HashMap<DomainName, SubDomainEntry>,
 }
 
 impl Settings {
@@ -32,6 +77,340 @@ impl Settings {
             .add_source(Environment::with_prefix("koppeln"))
             .build()?;
 
-        config.try_deserialize()
+        Self::from_config(config)
+    }
+
+    fn from_config(config: Config) -> Result<Self, ConfigError> {
+        let mut settings: Settings = config.try_deserialize()?;
+
+        legacy_listen(
+            "dns",
+            &mut settings.dns_listen,
+            settings.dns_address,
+            settings.dns_port,
+            DEFAULT_DNS_PORT,
+        )?;
+        legacy_listen(
+            "web",
+            &mut settings.web_listen,
+            settings.web_address,
+            settings.web_port,
+            DEFAULT_WEB_PORT,
+        )?;
+        if settings.dns_listen.is_empty() {
+            settings.dns_listen = vec![SocketAddr::from(([0, 0, 0, 0], DEFAULT_DNS_PORT))];
+        }
+
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    /// The zone configured at the top level followed by the additional
+    /// zones.
+    pub fn all_zones(&self) -> Vec<ZoneSettings> {
+        let primary = ZoneSettings {
+            soa: self.soa.clone(),
+            name_servers: self.name_servers.clone(),
+            caa: self.caa.clone(),
+            ttl: self.ttl.clone(),
+            addresses: self.addresses.clone(),
+        };
+
+        std::iter::once(primary)
+            .chain(self.zones.iter().cloned())
+            .collect()
+    }
+
+    /// The hosts of all zones, keyed by the zone apex.
+    pub fn storage_zones(&self) -> HashMap<DomainName, SubDomains> {
+        self.all_zones()
+            .into_iter()
+            .map(|zone| (zone.soa.mname, zone.addresses))
+            .collect()
+    }
+
+    /// All zones served by the DNS server, keyed by their apex. The reverse
+    /// zones share the SOA, name servers and TTLs of the top level zone.
+    pub fn dns_zones(&self) -> HashMap<DomainName, dns::server::Zone> {
+        let reverse_zones = self.reverse_zones.iter().map(|apex| {
+            let zone = dns::server::Zone {
+                soa: dns::StartOfAuthority {
+                    mname: apex.clone(),
+                    ..self.soa.clone()
+                },
+                name_servers: self.name_servers.clone(),
+                caa: vec![],
+                ttl: self.ttl.clone(),
+            };
+            (apex.clone(), zone)
+        });
+
+        self.all_zones()
+            .into_iter()
+            .map(|zone| {
+                let apex = zone.soa.mname.clone();
+                let zone = dns::server::Zone {
+                    soa: zone.soa,
+                    name_servers: zone.name_servers,
+                    caa: zone.caa,
+                    ttl: zone.ttl,
+                };
+                (apex, zone)
+            })
+            .chain(reverse_zones)
+            .collect()
+    }
+
+    /// The DNS parser accepts any octets as described in RFC 2181, so the
+    /// hostname syntax is enforced where the records are configured.
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.web_listen.is_empty() {
+            return Err(ConfigError::Message(
+                "at least one address is required in web_listen".to_string(),
+            ));
+        }
+
+        let zones = self.all_zones();
+
+        let mut apexes: Vec<&DomainName> = vec![];
+        for apex in zones
+            .iter()
+            .map(|zone| &zone.soa.mname)
+            .chain(&self.reverse_zones)
+        {
+            if apexes.contains(&apex) {
+                return Err(ConfigError::Message(format!("duplicate zone: {apex}")));
+            }
+            apexes.push(apex);
+        }
+
+        for zone in &zones {
+            zone.validate()?;
+        }
+
+        let arpa = [
+            DomainName::from("in-addr.arpa"),
+            DomainName::from("ip6.arpa"),
+        ];
+        if let Some(zone) = self
+            .reverse_zones
+            .iter()
+            .find(|zone| !arpa.iter().any(|arpa| zone.strip_suffix(arpa).is_some()))
+        {
+            return Err(ConfigError::Message(format!(
+                "invalid reverse zone: {zone}"
+            )));
+        }
+
+        if let Some(zone) = self
+            .reverse_zones
+            .iter()
+            .find(|zone| !zone.is_valid_hostname())
+        {
+            return Err(ConfigError::Message(format!("invalid hostname: {zone}")));
+        }
+
+        Ok(())
+    }
+}
+
+const DEFAULT_DNS_PORT: u16 = 53;
+const DEFAULT_WEB_PORT: u16 = 80;
+
+/// Maps the `<server>_address` and `<server>_port` keys of older configs to
+/// the listen addresses of the server.
+fn legacy_listen(
+    server: &str,
+    listen: &mut Vec<SocketAddr>,
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    default_port: u16,
+) -> Result<(), ConfigError> {
+    match (address, port) {
+        (None, None) => Ok(()),
+        _ if !listen.is_empty() => Err(ConfigError::Message(format!(
+            "{server}_address and {server}_port can't be combined with {server}_listen"
+        ))),
+        (Some(address), port) => {
+            log::warn!(
+                "{server}_address and {server}_port are deprecated, use {server}_listen instead"
+            );
+            *listen = vec![SocketAddr::new(address, port.unwrap_or(default_port))];
+            Ok(())
+        }
+        (None, Some(_)) => Err(ConfigError::Message(format!(
+            "{server}_port requires {server}_address"
+        ))),
+    }
+}
+
+impl ZoneSettings {
+    fn validate(&self) -> Result<(), ConfigError> {
+        // service names like `_sip._tcp.<host>` are only valid as owner
+        if let Some(name) = self
+            .addresses
+            .keys()
+            .find(|name| !name.is_valid_owner_name())
+        {
+            return Err(ConfigError::Message(format!("invalid hostname: {name}")));
+        }
+
+        if let Some(name) = self
+            .addresses
+            .iter()
+            .find_map(|(name, entry)| entry.is_alias_with_other_records().then_some(name))
+        {
+            return Err(ConfigError::Message(format!(
+                "alias can't have other records: {name}"
+            )));
+        }
+
+        if let Some(caa) = self
+            .caa
+            .iter()
+            .chain(self.addresses.values().flat_map(|entry| &entry.caa))
+            .find(|caa| !caa.is_valid())
+        {
+            return Err(ConfigError::Message(format!(
+                "invalid CAA tag: {}",
+                caa.tag
+            )));
+        }
+
+        let names = std::iter::once(&self.soa.mname)
+            .chain(&self.name_servers)
+            .chain(
+                self.addresses
+                    .values()
+                    .filter_map(|entry| entry.cname.as_ref()),
+            )
+            .chain(
+                self.addresses
+                    .values()
+                    .flat_map(|entry| entry.mx.iter().map(|mx| &mx.exchange)),
+            )
+            .chain(
+                self.addresses
+                    .values()
+                    .flat_map(|entry| entry.srv.iter().map(|srv| &srv.target)),
+            );
+        for name in names {
+            if !name.is_valid_hostname() {
+                return Err(ConfigError::Message(format!("invalid hostname: {name}")));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::FileFormat;
+
+    use super::*;
+
+    const ZONE: &str = r#"
+        [soa]
+        mname = "dyn.example.com"
+        rname = "postmaster.example.com"
+        serial = 1
+        refresh = 300
+        retry = 900
+        expire = 900
+        minimum = 300
+
+        [addresses]
+        "test" = { token = "super_secure" }
+    "#;
+
+    fn settings(listen: &str) -> Result<Settings, ConfigError> {
+        let config = Config::builder()
+            .add_source(File::from_str(
+                &format!("{listen}\n{ZONE}"),
+                FileFormat::Toml,
+            ))
+            .build()?;
+
+        Settings::from_config(config)
+    }
+
+    #[test]
+    fn it_maps_legacy_listen_addresses() {
+        let settings = settings(
+            r#"
+            dns_address = "127.0.0.1"
+            dns_port = 5354
+            web_address = "127.0.0.1"
+            web_port = 8088
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.dns_listen, vec!["127.0.0.1:5354".parse().unwrap()]);
+        assert_eq!(settings.web_listen, vec!["127.0.0.1:8088".parse().unwrap()]);
+    }
+
+    #[test]
+    fn it_defaults_legacy_ports() {
+        let settings = settings(
+            r#"
+            dns_address = "0.0.0.0"
+            web_address = "127.0.0.1"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.dns_listen, vec!["0.0.0.0:53".parse().unwrap()]);
+        assert_eq!(settings.web_listen, vec!["127.0.0.1:80".parse().unwrap()]);
+    }
+
+    #[test]
+    fn it_rejects_legacy_and_new_listen_addresses() {
+        let result = settings(
+            r#"
+            web_listen = ["127.0.0.1:8088"]
+            web_address = "0.0.0.0"
+            "#,
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_requires_web_listen_addresses() {
+        assert!(settings("").is_err());
+    }
+
+    #[test]
+    fn it_rejects_alias_with_other_records() {
+        let config = |entry: &str| {
+            let config = Config::builder()
+                .add_source(File::from_str(
+                    &format!("web_listen = [\"[::1]:8088\"]\n{ZONE}\"alias\" = {entry}"),
+                    FileFormat::Toml,
+                ))
+                .build()
+                .unwrap();
+            Settings::from_config(config)
+        };
+
+        assert!(config(r#"{ cname = "test.dyn.example.com", ttl = { cname = 60 } }"#).is_ok());
+        for entry in [
+            r#"{ cname = "test.dyn.example.com", txt = ["abc"] }"#,
+            r#"{ cname = "test.dyn.example.com", mx = [{ preference = 10, exchange = "mail.example.com" }] }"#,
+            r#"{ cname = "test.dyn.example.com", caa = [{ tag = "issue", value = "ca.example.net" }] }"#,
+            r#"{ cname = "test.dyn.example.com", ttl = { a = 60 } }"#,
+        ] {
+            assert!(config(entry).is_err(), "{entry}");
+        }
+    }
+
+    #[test]
+    fn it_defaults_dns_listen_addresses() {
+        let settings = settings(r#"web_listen = ["[::1]:8088"]"#).unwrap();
+
+        assert_eq!(settings.dns_listen, vec!["0.0.0.0:53".parse().unwrap()]);
     }
 }
@@ -1,41 +1,619 @@
 use std::{
     collections::HashMap,
-    net::{self},
+    net::{self, IpAddr},
+    sync::{Arc, Mutex, PoisonError},
 };
 
+use arc_swap::ArcSwap;
 use serde::{Deserialize, Serialize};
 
-use crate::dns::DomainName;
+use crate::dns::{self, DomainName};
+
+/// The storage shared by the DNS and the HTTP server. Queries read from a
+/// snapshot without any locking, while updates are applied to a copy that
+/// replaces the snapshot once it is complete. Clones refer to the same
+/// storage.
+#[derive(Debug, Clone)]
+pub struct SharedStorage {
+    snapshot: Arc<ArcSwap<Storage>>,
+    /// Serializes updates, so that none of them gets lost
+    updates: Arc<Mutex<()>>,
+}
+
+impl SharedStorage {
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            snapshot: Arc::new(ArcSwap::from_pointee(storage)),
+            updates: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// The current snapshot, which isn't affected by later updates.
+    pub fn load(&self) -> Arc<Storage> {
+        self.snapshot.load_full()
+    }
+
+    /// Applies `f` to a copy of the current snapshot. The copy is only
+    /// published if `f` succeeds, so readers never see partial updates.
+    pub fn update<T, E>(&self, f: impl FnOnce(&mut Storage) -> Result<T, E>) -> Result<T, E> {
+        // the lock doesn't protect any data, so a panic while holding it
+        // can't leave anything in an inconsistent state
+        let _guard = self.updates.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut storage = Storage::clone(&self.snapshot.load());
+        let result = f(&mut storage)?;
+        self.snapshot.store(Arc::new(storage));
+
+        Ok(result)
+    }
+}
+
+/// Entries of a zone, keyed by their name relative to the zone apex
+pub type SubDomains = HashMap<DomainName, SubDomainEntry>;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Storage {
-    mname: DomainName,
-    sub_domains: HashMap<DomainName, SubDomainEntry>,
+    /// Entries of all zones, keyed by the zone apex
+    zones: HashMap<DomainName, SubDomains>,
+    /// Hostnames of the current addresses, keyed by the reverse name of the
+    /// address
+    #[serde(default)]
+    pointers: HashMap<DomainName, Vec<DomainName>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct SubDomainEntry {
     #[serde(skip)]
     pub ipv4: Option<net::Ipv4Addr>,
     #[serde(skip)]
     pub ipv6: Option<net::Ipv6Addr>,
+    /// Entries without a token can't be updated
+    #[serde(default)]
     pub token: String,
+    /// Makes the entry an alias of another name, which can't have any other
+    /// records
+    #[serde(default)]
+    pub cname: Option<DomainName>,
+    #[serde(default)]
+    pub txt: Vec<String>,
+    #[serde(default)]
+    pub mx: Vec<MailExchange>,
+    #[serde(default)]
+    pub srv: Vec<Service>,
+    #[serde(default)]
+    pub caa: Vec<CaaProperty>,
+    /// Overrides the TTLs of the zone for the records of this entry
+    #[serde(default)]
+    pub ttl: Ttls,
+}
+
+impl SubDomainEntry {
+    /// Whether the entry is an alias that also holds other records or
+    /// settings of them, which an alias can't have, see
+    /// [RFC 1034](https://www.rfc-editor.org/rfc/rfc1034#section-3.6.2).
+    /// Only the TTL of the CNAME record itself can be configured.
+    pub fn is_alias_with_other_records(&self) -> bool {
+        let other_ttls = Ttls {
+            default: None,
+            cname: None,
+            ..self.ttl.clone()
+        };
+
+        self.cname.is_some()
+            && (self.ipv4.is_some()
+                || self.ipv6.is_some()
+                || !self.txt.is_empty()
+                || !self.mx.is_empty()
+                || !self.srv.is_empty()
+                || !self.caa.is_empty()
+                || other_ttls != Ttls::default())
+    }
+}
+
+/// TTLs of records in seconds. A TTL configured for a record type takes
+/// precedence over the default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Ttls {
+    pub default: Option<u32>,
+    pub a: Option<u32>,
+    pub aaaa: Option<u32>,
+    pub cname: Option<u32>,
+    pub mx: Option<u32>,
+    pub srv: Option<u32>,
+    pub txt: Option<u32>,
+    pub caa: Option<u32>,
+    pub ns: Option<u32>,
+    pub ptr: Option<u32>,
+    /// Negative responses are cached for at most the MINIMUM field of the
+    /// SOA, even if this is higher
+    pub soa: Option<u32>,
+}
+
+impl Ttls {
+    pub fn get(&self, record_type: dns::QueryType) -> Option<u32> {
+        let ttl = match record_type {
+            dns::QueryType::A => self.a,
+            dns::QueryType::AAAA => self.aaaa,
+            dns::QueryType::CNAME => self.cname,
+            dns::QueryType::MX => self.mx,
+            dns::QueryType::SRV => self.srv,
+            dns::QueryType::TXT => self.txt,
+            dns::QueryType::CAA => self.caa,
+            dns::QueryType::NS => self.ns,
+            dns::QueryType::PTR => self.ptr,
+            dns::QueryType::SOA => self.soa,
+            _ => None,
+        };
+
+        ttl.or(self.default)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MailExchange {
+    /// Lower values are preferred
+    pub preference: u16,
+    pub exchange: DomainName,
+}
+
+/// Location of a service, see
+/// [RFC 2782](https://www.rfc-editor.org/rfc/rfc2782).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Service {
+    /// Lower values are preferred
+    pub priority: u16,
+    /// Relative weight of targets with the same priority
+    pub weight: u16,
+    pub port: u16,
+    pub target: DomainName,
+}
+
+/// Restricts which certificate authorities may issue certificates for a
+/// name, see [RFC 8659](https://www.rfc-editor.org/rfc/rfc8659#section-4).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CaaProperty {
+    /// Only the issuer critical flag (128) is defined
+    #[serde(default)]
+    pub flags: u8,
+    /// E.g. `issue`, `issuewild` or `iodef`
+    pub tag: String,
+    pub value: String,
+}
+
+impl CaaProperty {
+    /// Tags consist of 1 to 15 letters and digits.
+    pub fn is_valid(&self) -> bool {
+        (1..=15).contains(&self.tag.len()) && self.tag.chars().all(|c| c.is_ascii_alphanumeric())
+    }
 }
 
 impl Storage {
-    pub fn new(mname: DomainName, sub_domains: HashMap<DomainName, SubDomainEntry>) -> Self {
-        Self { mname, sub_domains }
+    pub fn new(zones: HashMap<DomainName, SubDomains>) -> Self {
+        Self {
+            zones,
+            pointers: HashMap::new(),
+        }
     }
 
+    /// Returns the entry of the name or of the wildcard matching it.
     pub fn get(&self, name: &DomainName) -> Option<&SubDomainEntry> {
-        let sub_domain = name.strip_suffix(&self.mname)?;
+        let (sub_domains, sub_domain) = self.zone(name)?;
+        let sub_domain = sub_domain?;
+
+        sub_domains
+            .get(&sub_domain)
+            .or_else(|| wildcard(sub_domains, &sub_domain))
+    }
+
+    /// Checks whether the name exists in one of the zones. This is also the
+    /// case for empty non-terminals, i.e. names without an entry of their
+    /// own but with entries below them, see
+    /// [RFC 8020](https://www.rfc-editor.org/rfc/rfc8020).
+    pub fn contains(&self, name: &DomainName) -> bool {
+        match self.zone(name) {
+            // the apex itself
+            Some((_, None)) => true,
+            Some((sub_domains, Some(sub_domain))) => {
+                contains_name(sub_domains.keys(), &sub_domain)
+                    || wildcard(sub_domains, &sub_domain).is_some()
+            }
+            None => contains_name(self.pointers.keys(), name),
+        }
+    }
+
+    /// Finds the zone of the name by the longest matching apex. Returns the
+    /// entries of the zone and the name relative to the apex, which is
+    /// `None` for the apex itself.
+    fn zone(&self, name: &DomainName) -> Option<(&SubDomains, Option<DomainName>)> {
+        let apex = self.apex(name)?;
+
+        Some((&self.zones[apex], name.strip_suffix(apex)))
+    }
+
+    fn zone_mut(&mut self, name: &DomainName) -> Option<(&mut SubDomains, Option<DomainName>)> {
+        let apex = self.apex(name)?.clone();
+        let sub_domain = name.strip_suffix(&apex);
+
+        Some((self.zones.get_mut(&apex)?, sub_domain))
+    }
+
+    fn apex(&self, name: &DomainName) -> Option<&DomainName> {
+        self.zones
+            .keys()
+            .filter(|apex| name.is_subdomain_of(apex))
+            .max_by_key(|apex| apex.labels().count())
+    }
+
+    /// Hostnames of the address with the given reverse name.
+    pub fn pointers(&self, name: &DomainName) -> &[DomainName] {
+        self.pointers
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
 
-        self.sub_domains.get(&sub_domain)
+    pub fn insert_pointer(&mut self, addr: IpAddr, hostname: DomainName) {
+        let hostnames = self.pointers.entry(dns::reverse_name(addr)).or_default();
+        if !hostnames.contains(&hostname) {
+            hostnames.push(hostname);
+        }
+    }
+
+    pub fn remove_pointer(&mut self, addr: IpAddr, hostname: &DomainName) {
+        let name = dns::reverse_name(addr);
+        let Some(hostnames) = self.pointers.get_mut(&name) else {
+            return;
+        };
+
+        hostnames.retain(|name| name != hostname);
+        if hostnames.is_empty() {
+            self.pointers.remove(&name);
+        }
     }
 
     pub fn get_mut(&mut self, name: &DomainName) -> Option<&mut SubDomainEntry> {
-        let sub_domain = name.strip_suffix(&self.mname)?;
+        let (sub_domains, sub_domain) = self.zone_mut(name)?;
+
+        sub_domains.get_mut(&sub_domain?)
+    }
+
+    /// Returns the entry of the name or creates an empty one, if the name is
+    /// part of a zone.
+    pub fn get_or_insert(&mut self, name: &DomainName) -> Option<&mut SubDomainEntry> {
+        let (sub_domains, sub_domain) = self.zone_mut(name)?;
+
+        Some(sub_domains.entry(sub_domain?).or_default())
+    }
+
+    /// Removes the entry of the name, if it doesn't hold any data anymore.
+    pub fn remove_if_empty(&mut self, name: &DomainName) {
+        let Some((sub_domains, Some(sub_domain))) = self.zone_mut(name) else {
+            return;
+        };
+
+        if sub_domains
+            .get(&sub_domain)
+            .is_some_and(|entry| *entry == SubDomainEntry::default())
+        {
+            sub_domains.remove(&sub_domain);
+        }
+    }
+
+    /// Finds the entry that owns the name, which is the closest entry with
+    /// a token at or above the name. Its token grants access to the records
+    /// of all names below it, e.g. `_acme-challenge.<host>`.
+    pub fn owner(&self, name: &DomainName) -> Option<&SubDomainEntry> {
+        let (sub_domains, mut sub_domain) = self.zone(name)?;
+
+        while let Some(name) = sub_domain {
+            match sub_domains.get(&name) {
+                Some(entry) if !entry.token.is_empty() => return Some(entry),
+                _ => sub_domain = name.parent(),
+            }
+        }
+
+        None
+    }
+}
+
+/// Finds the wildcard entry matching a name relative to the apex. Only names
+/// that don't exist are matched and only by the wildcard below their closest
+/// existing ancestor, see
+/// [RFC 4592](https://www.rfc-editor.org/rfc/rfc4592#section-3.3.1).
+fn wildcard<'a>(
+    sub_domains: &'a SubDomains,
+    sub_domain: &DomainName,
+) -> Option<&'a SubDomainEntry> {
+    if contains_name(sub_domains.keys(), sub_domain) {
+        return None;
+    }
+
+    let mut closest_encloser = sub_domain.parent();
+    while let Some(name) = &closest_encloser {
+        if contains_name(sub_domains.keys(), name) {
+            break;
+        }
+        closest_encloser = name.parent();
+    }
+
+    let wildcard = match closest_encloser {
+        Some(name) => DomainName::from(format!("*.{name}")),
+        None => DomainName::from("*"),
+    };
+    sub_domains.get(&wildcard)
+}
+
+/// Whether the name is one of the keys or an empty non-terminal above them.
+fn contains_name<'a>(mut keys: impl Iterator<Item = &'a DomainName>, name: &DomainName) -> bool {
+    keys.any(|key| key == name || key.strip_suffix(name).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage() -> Storage {
+        let entry = SubDomainEntry {
+            token: "super_secure".to_string(),
+            ..Default::default()
+        };
+
+        Storage::new(HashMap::from([(
+            DomainName::from("dyn.example.com"),
+            HashMap::from([(DomainName::from("host.lab"), entry)]),
+        )]))
+    }
+
+    #[test]
+    fn it_keeps_snapshot_unchanged_by_updates() {
+        let shared = SharedStorage::new(storage());
+        let name = DomainName::from("host.lab.dyn.example.com");
+        let snapshot = shared.load();
+
+        let result = shared.update(|storage| {
+            storage
+                .get_mut(&name)
+                .ok_or(())?
+                .txt
+                .push("new".to_string());
+            Ok::<_, ()>(())
+        });
+
+        assert_eq!(result, Ok(()));
+        assert!(snapshot.get(&name).unwrap().txt.is_empty());
+        assert_eq!(shared.load().get(&name).unwrap().txt, vec!["new"]);
+    }
+
+    #[test]
+    fn it_discards_failed_updates() {
+        let shared = SharedStorage::new(storage());
+        let name = DomainName::from("host.lab.dyn.example.com");
+
+        let result = shared.update(|storage| {
+            storage
+                .get_mut(&name)
+                .ok_or(())?
+                .txt
+                .push("new".to_string());
+            Err::<(), _>(())
+        });
+
+        assert_eq!(result, Err(()));
+        assert!(shared.load().get(&name).unwrap().txt.is_empty());
+    }
+
+    #[test]
+    fn it_ignores_names_with_escaped_dot_in_zone_label() {
+        let mut storage = storage();
+        let name = DomainName::from(r"host.lab\.dyn.example.com");
+
+        assert!(!storage.contains(&name));
+        assert!(storage.get_or_insert(&name).is_none());
+    }
+
+    #[test]
+    fn it_routes_names_to_zone_with_longest_apex() {
+        let entry = |token: &str| SubDomainEntry {
+            token: token.to_string(),
+            ..Default::default()
+        };
+        let storage = Storage::new(HashMap::from([
+            (
+                DomainName::from("example.com"),
+                HashMap::from([(DomainName::from("host.dyn"), entry("outer"))]),
+            ),
+            (
+                DomainName::from("dyn.example.com"),
+                HashMap::from([(DomainName::from("host"), entry("inner"))]),
+            ),
+            (
+                DomainName::from("example.net"),
+                HashMap::from([(DomainName::from("host"), entry("other"))]),
+            ),
+        ]));
+
+        let token = |name: &str| {
+            storage
+                .get(&DomainName::from(name))
+                .map(|entry| entry.token.as_str())
+        };
+        assert_eq!(token("host.dyn.example.com"), Some("inner"));
+        assert_eq!(token("host.example.net"), Some("other"));
+        assert_eq!(token("host.example.org"), None);
+        assert!(storage.contains(&DomainName::from("example.net")));
+        assert!(!storage.contains(&DomainName::from("other.example.net")));
+    }
+
+    #[test]
+    fn it_prefers_ttl_of_record_type() {
+        let ttls = Ttls {
+            default: Some(60),
+            aaaa: Some(30),
+            ..Default::default()
+        };
+
+        assert_eq!(ttls.get(dns::QueryType::AAAA), Some(30));
+        assert_eq!(ttls.get(dns::QueryType::A), Some(60));
+        assert_eq!(Ttls::default().get(dns::QueryType::A), None);
+    }
+
+    #[test]
+    fn it_contains_apex_entries_and_empty_non_terminals() {
+        let storage = storage();
+
+        assert!(storage.contains(&DomainName::from("dyn.example.com")));
+        assert!(storage.contains(&DomainName::from("host.lab.dyn.example.com")));
+        assert!(storage.contains(&DomainName::from("lab.dyn.example.com")));
+    }
+
+    fn wildcard_storage() -> Storage {
+        let entry = |ip: [u8; 4]| SubDomainEntry {
+            ipv4: Some(net::Ipv4Addr::from(ip)),
+            ..Default::default()
+        };
+
+        Storage::new(HashMap::from([(
+            DomainName::from("dyn.example.com"),
+            HashMap::from([
+                (DomainName::from("*.home"), entry([10, 0, 0, 1])),
+                (DomainName::from("nas.home"), entry([10, 0, 0, 2])),
+                (DomainName::from("host.lab.home"), entry([10, 0, 0, 3])),
+            ]),
+        )]))
+    }
+
+    fn ipv4(storage: &Storage, name: &str) -> Option<net::Ipv4Addr> {
+        storage.get(&DomainName::from(name)).and_then(|e| e.ipv4)
+    }
+
+    #[test]
+    fn it_matches_wildcards() {
+        let storage = wildcard_storage();
+
+        assert_eq!(
+            ipv4(&storage, "www.home.dyn.example.com"),
+            Some(net::Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(
+            ipv4(&storage, "a.b.home.dyn.example.com"),
+            Some(net::Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert!(storage.contains(&DomainName::from("www.home.dyn.example.com")));
+    }
+
+    #[test]
+    fn it_prefers_explicit_entries_over_wildcards() {
+        let storage = wildcard_storage();
+
+        assert_eq!(
+            ipv4(&storage, "nas.home.dyn.example.com"),
+            Some(net::Ipv4Addr::new(10, 0, 0, 2))
+        );
+    }
+
+    #[test]
+    fn it_does_not_match_wildcards_below_existing_names() {
+        let storage = wildcard_storage();
+
+        // an empty non-terminal exists, so it isn't matched by the wildcard
+        assert!(
+            storage
+                .get(&DomainName::from("lab.home.dyn.example.com"))
+                .is_none()
+        );
+        // the closest encloser is `lab.home`, which has no wildcard
+        assert!(
+            storage
+                .get(&DomainName::from("www.lab.home.dyn.example.com"))
+                .is_none()
+        );
+        assert!(!storage.contains(&DomainName::from("www.lab.home.dyn.example.com")));
+        assert!(
+            storage
+                .get(&DomainName::from("www.dyn.example.com"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn it_contains_reverse_names_of_addresses() {
+        let mut storage = storage();
+        let host = DomainName::from("host.lab.dyn.example.com");
+        storage.insert_pointer(IpAddr::from([192, 0, 2, 10]), host.clone());
+
+        assert!(storage.contains(&DomainName::from("10.2.0.192.in-addr.arpa")));
+        assert!(storage.contains(&DomainName::from("2.0.192.in-addr.arpa")));
+        assert!(!storage.contains(&DomainName::from("11.2.0.192.in-addr.arpa")));
+    }
+
+    #[test]
+    fn it_keeps_pointers_of_hosts_sharing_an_address() {
+        let mut storage = storage();
+        let addr = IpAddr::from([192, 0, 2, 10]);
+        let name = dns::reverse_name(addr);
+        let first = DomainName::from("first.dyn.example.com");
+        let second = DomainName::from("second.dyn.example.com");
+
+        storage.insert_pointer(addr, first.clone());
+        storage.insert_pointer(addr, second.clone());
+        storage.insert_pointer(addr, second.clone());
+        assert_eq!(
+            storage.pointers(&name).to_vec(),
+            vec![first.clone(), second.clone()]
+        );
+
+        storage.remove_pointer(addr, &first);
+        assert_eq!(storage.pointers(&name).to_vec(), vec![second.clone()]);
+
+        storage.remove_pointer(addr, &second);
+        assert!(storage.pointers(&name).is_empty());
+        assert!(!storage.contains(&name));
+    }
+
+    #[test]
+    fn it_finds_owner_of_names_below_an_entry() {
+        let storage = storage();
+
+        let owner = storage.owner(&DomainName::from(
+            "_acme-challenge.host.lab.dyn.example.com",
+        ));
+
+        assert_eq!(owner.unwrap().token, "super_secure");
+        assert!(
+            storage
+                .owner(&DomainName::from("lab.dyn.example.com"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn it_removes_empty_entries() {
+        let mut storage = storage();
+        let name = DomainName::from("_acme-challenge.host.lab.dyn.example.com");
+
+        storage
+            .get_or_insert(&name)
+            .unwrap()
+            .txt
+            .push("challenge".to_string());
+        storage.remove_if_empty(&name);
+        assert!(storage.get(&name).is_some());
+
+        storage.get_mut(&name).unwrap().txt.clear();
+        storage.remove_if_empty(&name);
+        assert!(storage.get(&name).is_none());
+
+        let host = DomainName::from("host.lab.dyn.example.com");
+        storage.remove_if_empty(&host);
+        assert!(storage.get(&host).is_some());
+    }
+
+    #[test]
+    fn it_does_not_contain_unknown_names() {
+        let storage = storage();
 
-        self.sub_domains.get_mut(&sub_domain)
+        assert!(!storage.contains(&DomainName::from("ab.dyn.example.com")));
+        assert!(!storage.contains(&DomainName::from("other.lab.dyn.example.com")));
+        assert!(!storage.contains(&DomainName::from("example.com")));
     }
 }
@@ -1,7 +1,10 @@
 use std::convert::Infallible;
-use std::net::{IpAddr, SocketAddr};
+use std::net::IpAddr;
 
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use tokio::net::TcpListener;
+use tokio::task::{JoinError, JoinSet};
 use warp;
 use warp::Filter;
 use warp::http::StatusCode;
@@ -16,12 +19,36 @@ pub struct UpdateInfo {
     pub ip: IpAddr,
 }
 
-pub async fn create_update_server(address: SocketAddr, storage: SharedStorage) {
-    warp::serve(update_address(storage))
-        .bind(address)
-        .await
-        .run()
-        .await;
+#[derive(Debug, Deserialize, Clone)]
+pub struct TxtInfo {
+    pub hostname: String,
+    /// The value to add or remove. Removing without a value clears all
+    /// values of the hostname.
+    pub value: Option<String>,
+}
+
+/// Serves the API on all given listeners, each in its own task.
+pub async fn create_update_server(
+    listeners: Vec<TcpListener>,
+    storage: SharedStorage,
+) -> Result<(), JoinError> {
+    let routes = update_address(storage.clone())
+        .or(update_txt(storage.clone()))
+        .or(delete_txt(storage));
+
+    let mut tasks = JoinSet::new();
+    for listener in listeners {
+        if let Ok(addr) = listener.local_addr() {
+            log::info!("HTTP server now listening on: {addr}");
+        }
+        tasks.spawn(warp::serve(routes.clone()).incoming(listener).run());
+    }
+
+    while let Some(res) = tasks.join_next().await {
+        res?;
+    }
+
+    Ok(())
 }
 
 pub fn update_address(
@@ -36,6 +63,30 @@ pub fn update_address(
         .recover(handle_missing_auth_header)
 }
 
+pub fn update_txt(
+    storage: SharedStorage,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("txt")
+        .and(warp::put())
+        .and(with_token())
+        .and(json_body())
+        .and(with_storage(storage))
+        .and_then(update_txt_handler)
+        .recover(handle_missing_auth_header)
+}
+
+pub fn delete_txt(
+    storage: SharedStorage,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("txt")
+        .and(warp::delete())
+        .and(with_token())
+        .and(json_body())
+        .and(with_storage(storage))
+        .and_then(delete_txt_handler)
+        .recover(handle_missing_auth_header)
+}
+
 async fn handle_missing_auth_header(
     rejection: warp::Rejection,
 ) -> Result<impl warp::Reply, warp::Rejection> {
@@ -56,7 +107,8 @@ fn with_storage(
     warp::any().map(move || storage.clone())
 }
 
-fn json_body() -> impl Filter<Extract = (UpdateInfo,), Error = warp::Rejection> + Clone {
+fn json_body<T: DeserializeOwned + Send>()
+-> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
 }
 
@@ -65,27 +117,260 @@ pub async fn update_address_handler(
     update_info: UpdateInfo,
     storage: SharedStorage,
 ) -> Result<impl warp::Reply, Infallible> {
-    let mut storage = match storage.lock() {
-        Ok(storage) => storage,
-        Err(err) => {
-            log::error!("failed to lock storage: {err}");
-            return Ok(StatusCode::INTERNAL_SERVER_ERROR);
+    let domain_name = DomainName::from(update_info.hostname);
+
+    let status = storage.update(|storage| {
+        let Some(addr) = storage.get_mut(&domain_name) else {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        };
+
+        // entries without a token are not meant to be updated
+        if addr.token.is_empty() || token != addr.token {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        // an alias can't have any other records
+        if addr.cname.is_some() {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        let old_ip = match update_info.ip {
+            IpAddr::V4(ipv4) => addr.ipv4.replace(ipv4).map(IpAddr::V4),
+            IpAddr::V6(ipv6) => addr.ipv6.replace(ipv6).map(IpAddr::V6),
+        };
+
+        // keep the reverse zones in sync
+        if let Some(old_ip) = old_ip {
+            storage.remove_pointer(old_ip, &domain_name);
         }
+        storage.insert_pointer(update_info.ip, domain_name.clone());
+
+        Ok(StatusCode::NO_CONTENT)
+    });
+
+    Ok(status.unwrap_or_else(|status| status))
+}
+
+/// Adds a TXT value, e.g. for an ACME DNS-01 challenge at
+/// `_acme-challenge.<host>`. The token of the host grants access to all
+/// names below it.
+pub async fn update_txt_handler(
+    token: String,
+    txt_info: TxtInfo,
+    storage: SharedStorage,
+) -> Result<impl warp::Reply, Infallible> {
+    let Some(value) = txt_info.value else {
+        return Ok(StatusCode::BAD_REQUEST);
     };
+    let domain_name = DomainName::from(txt_info.hostname);
 
-    let domain_name = DomainName::from(update_info.hostname);
-    let Some(addr) = storage.get_mut(&domain_name) else {
+    // names are created on demand, but must neither be malformed nor become
+    // a wildcard that matches all other names below the host
+    if !domain_name.is_valid_owner_name() || domain_name.labels().any(|label| label == "*") {
         return Ok(StatusCode::UNPROCESSABLE_ENTITY);
-    };
+    }
+
+    let status = storage.update(|storage| {
+        let Some(owner) = storage.owner(&domain_name) else {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        };
+
+        if token != owner.token {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        let Some(entry) = storage.get_or_insert(&domain_name) else {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        };
+
+        // an alias can't have any other records
+        if entry.cname.is_some() {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        if !entry.txt.contains(&value) {
+            entry.txt.push(value);
+        }
 
-    if token != addr.token {
-        return Ok(StatusCode::FORBIDDEN);
+        Ok(StatusCode::NO_CONTENT)
+    });
+
+    Ok(status.unwrap_or_else(|status| status))
+}
+
+/// Removes a single TXT value or all of them, if no value is given.
+pub async fn delete_txt_handler(
+    token: String,
+    txt_info: TxtInfo,
+    storage: SharedStorage,
+) -> Result<impl warp::Reply, Infallible> {
+    let domain_name = DomainName::from(txt_info.hostname);
+
+    let status = storage.update(|storage| {
+        let Some(owner) = storage.owner(&domain_name) else {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        };
+
+        if token != owner.token {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        if let Some(entry) = storage.get_mut(&domain_name) {
+            match txt_info.value {
+                Some(value) => entry.txt.retain(|txt| *txt != value),
+                None => entry.txt.clear(),
+            }
+        }
+        // drop entries that were only created to hold the TXT values
+        storage.remove_if_empty(&domain_name);
+
+        Ok(StatusCode::NO_CONTENT)
+    });
+
+    Ok(status.unwrap_or_else(|status| status))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::storage::{Storage, SubDomainEntry};
+
+    const CHALLENGE: &str = "_acme-challenge.home.dyn.example.com";
+
+    fn storage() -> SharedStorage {
+        let sub_domains = HashMap::from([(
+            DomainName::from("home"),
+            SubDomainEntry {
+                token: "super_secure".to_string(),
+                ..Default::default()
+            },
+        )]);
+
+        SharedStorage::new(Storage::new(HashMap::from([(
+            DomainName::from("dyn.example.com"),
+            sub_domains,
+        )])))
+    }
+
+    fn txt(storage: &SharedStorage, name: &str) -> Option<Vec<String>> {
+        storage
+            .load()
+            .get(&DomainName::from(name))
+            .map(|entry| entry.txt.clone())
     }
 
-    match update_info.ip {
-        IpAddr::V4(ipv4) => addr.ipv4 = Some(ipv4),
-        IpAddr::V6(ipv6) => addr.ipv6 = Some(ipv6),
+    async fn request(storage: &SharedStorage, method: &str, token: &str, body: &str) -> StatusCode {
+        let routes = update_txt(storage.clone()).or(delete_txt(storage.clone()));
+
+        warp::test::request()
+            .method(method)
+            .path("/txt")
+            .header("authorization", token)
+            .body(body)
+            .reply(&routes)
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn it_updates_pointer_of_address() {
+        let storage = storage();
+        let routes = update_address(storage.clone());
+        let hostname = DomainName::from("home.dyn.example.com");
+
+        for ip in ["192.0.2.1", "192.0.2.2"] {
+            let status = warp::test::request()
+                .method("PUT")
+                .path("/hostname")
+                .header("authorization", "super_secure")
+                .body(format!(r#"{{"hostname": "{hostname}", "ip": "{ip}"}}"#))
+                .reply(&routes)
+                .await
+                .status();
+            assert_eq!(status, StatusCode::NO_CONTENT);
+        }
+
+        let storage = storage.load();
+        let pointers = |name: &str| storage.pointers(&DomainName::from(name)).to_vec();
+        assert!(pointers("1.2.0.192.in-addr.arpa").is_empty());
+        assert_eq!(pointers("2.2.0.192.in-addr.arpa"), vec![hostname]);
     }
 
-    Ok(StatusCode::NO_CONTENT)
+    #[tokio::test]
+    async fn it_adds_txt_values_below_host() {
+        let storage = storage();
+        let body = format!(r#"{{"hostname": "{CHALLENGE}", "value": "abc"}}"#);
+
+        let status = request(&storage, "PUT", "super_secure", &body).await;
+        request(&storage, "PUT", "super_secure", &body).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(txt(&storage, CHALLENGE), Some(vec!["abc".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn it_rejects_wrong_token() {
+        let storage = storage();
+        let body = format!(r#"{{"hostname": "{CHALLENGE}", "value": "abc"}}"#);
+
+        let status = request(&storage, "PUT", "wrong", &body).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(txt(&storage, CHALLENGE), None);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_names_without_owner() {
+        let storage = storage();
+        let body = r#"{"hostname": "_acme-challenge.other.dyn.example.com", "value": "abc"}"#;
+
+        let status = request(&storage, "PUT", "super_secure", body).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_invalid_names() {
+        let storage = storage();
+
+        for hostname in ["*.home.dyn.example.com", "in valid.home.dyn.example.com"] {
+            let body = format!(r#"{{"hostname": "{hostname}", "value": "abc"}}"#);
+
+            let status = request(&storage, "PUT", "super_secure", &body).await;
+
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(txt(&storage, hostname), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_removes_single_txt_value() {
+        let storage = storage();
+        for value in ["abc", "def"] {
+            let body = format!(r#"{{"hostname": "{CHALLENGE}", "value": "{value}"}}"#);
+            request(&storage, "PUT", "super_secure", &body).await;
+        }
+
+        let body = format!(r#"{{"hostname": "{CHALLENGE}", "value": "abc"}}"#);
+        let status = request(&storage, "DELETE", "super_secure", &body).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(txt(&storage, CHALLENGE), Some(vec!["def".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn it_clears_txt_values_and_removes_entry() {
+        let storage = storage();
+        let body = format!(r#"{{"hostname": "{CHALLENGE}", "value": "abc"}}"#);
+        request(&storage, "PUT", "super_secure", &body).await;
+
+        let body = format!(r#"{{"hostname": "{CHALLENGE}"}}"#);
+        let status = request(&storage, "DELETE", "super_secure", &body).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(txt(&storage, CHALLENGE), None);
+        assert!(txt(&storage, "home.dyn.example.com").is_some());
+    }
 }